@@ -0,0 +1,1624 @@
+//! A MARS compatible assembler, so that `.asm`/`.s` files can be run without a cross toolchain
+
+use std::{collections::HashMap, fmt::Display, ops::Range};
+
+use crate::{
+    inst::{Bshfl, Cop0Op, Fmt, FpuFunc, Func, Inst, InstKind, RegImm, Special2, Special3},
+    memmap::MemoryMap,
+    reg::{AT, RA, REGS},
+};
+
+macro_rules! bail {
+    ($($arg: tt)*) => {
+        return Err(format!($($arg)*))
+    };
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// A contiguous chunk of assembled memory
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Section {
+    pub base: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl Section {
+    fn new(base: usize) -> Self {
+        Self {
+            base,
+            bytes: Vec::new(),
+        }
+    }
+
+    /// The address that the next byte will be placed at
+    pub fn addr(&self) -> usize {
+        self.base + self.bytes.len()
+    }
+
+    fn align(&mut self, align: usize) {
+        let len = self.addr().next_multiple_of(align) - self.base;
+        self.bytes.resize(len, 0);
+    }
+}
+
+/// A line of source along with the instructions that it assembled into
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceLine {
+    pub line: usize,
+    pub text: String,
+    pub addrs: Range<usize>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Assembled {
+    pub text: Section,
+    pub data: Section,
+    /// Kernel text and data, for exception handlers
+    pub ktext: Section,
+    pub kdata: Section,
+    pub labels: HashMap<String, usize>,
+    pub lines: Vec<SourceLine>,
+}
+
+impl Assembled {
+    /// MARS starts execution at the beginning of `.text`, but `__start` is used if it exists so
+    /// that the sources written for the toolchain in `c/` run too.
+    pub fn entry(&self) -> usize {
+        self.labels
+            .get("__start")
+            .copied()
+            .unwrap_or(self.text.base)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Expr {
+    Int(i64),
+    /// label + offset
+    Label(String, i64),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Operand {
+    Reg(u8),
+    /// A floating point register, `$f0`
+    FReg(u8),
+    Expr(Expr),
+    /// offset($base)
+    Mem(Expr, u8),
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+enum SectionKind {
+    #[default]
+    Text,
+    Data,
+    KText,
+    KData,
+}
+
+impl SectionKind {
+    fn is_text(self) -> bool {
+        matches!(self, SectionKind::Text | SectionKind::KText)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Statement {
+    line: usize,
+    source: String,
+    section: SectionKind,
+    addr: usize,
+    /// Number of instructions this expands to
+    len: usize,
+    mnemonic: String,
+    operands: Vec<Operand>,
+}
+
+/// A data value that refers to a label, so can only be written once all labels are known
+#[derive(Clone, Debug)]
+struct Fixup {
+    line: usize,
+    section: SectionKind,
+    addr: usize,
+    size: usize,
+    expr: Expr,
+}
+
+#[derive(Default)]
+struct Assembler {
+    line: usize,
+    text: Section,
+    data: Section,
+    ktext: Section,
+    kdata: Section,
+    section: SectionKind,
+    labels: HashMap<String, usize>,
+    /// Labels that will be placed at the next piece of content, so they follow auto-alignment
+    pending_labels: Vec<String>,
+    statements: Vec<Statement>,
+    fixups: Vec<Fixup>,
+    /// Set once every label is known, before then unknown labels resolve to 0 so that the size
+    /// of pseudo-instructions can be found
+    resolved: bool,
+    /// Whether the program runs with delay slots, branches that pseudo-instructions expand to
+    /// need a `nop` after them then
+    delay_slots: bool,
+}
+
+/// Assemble a MARS style program, the sections start at the addresses in `map`
+pub fn assemble(src: &str, map: &MemoryMap, delay_slots: bool) -> Result<Assembled, AsmError> {
+    let mut asm = Assembler {
+        text: Section::new(map.text),
+        data: Section::new(map.data),
+        ktext: Section::new(map.ktext),
+        kdata: Section::new(map.kdata),
+        delay_slots,
+        ..Default::default()
+    };
+    for (i, line) in src.lines().enumerate() {
+        asm.line = i + 1;
+        asm.parse_line(line).map_err(|msg| AsmError { line: i + 1, msg })?;
+    }
+    asm.finish()
+}
+
+impl Assembler {
+    fn section(&mut self) -> &mut Section {
+        match self.section {
+            SectionKind::Text => &mut self.text,
+            SectionKind::Data => &mut self.data,
+            SectionKind::KText => &mut self.ktext,
+            SectionKind::KData => &mut self.kdata,
+        }
+    }
+
+    fn place_labels(&mut self) -> Result<(), String> {
+        let addr = self.section().addr();
+        for label in std::mem::take(&mut self.pending_labels) {
+            if self.labels.insert(label.clone(), addr).is_some() {
+                bail!("label `{}` is defined more than once", label);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let mut line = strip_comment(line).trim();
+        while let Some((label, rest)) = split_label(line) {
+            self.pending_labels.push(label.to_string());
+            line = rest.trim_start();
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let (mnemonic, operands) = line
+            .split_once(char::is_whitespace)
+            .unwrap_or((line, ""));
+        let mnemonic = mnemonic.to_ascii_lowercase();
+        let operands = split_operands(operands)?;
+
+        if mnemonic.starts_with('.') {
+            self.directive(&mnemonic, &operands)
+        } else {
+            let operands = operands
+                .into_iter()
+                .map(parse_operand)
+                .collect::<Result<_, _>>()?;
+            self.instruction(line, mnemonic, operands)
+        }
+    }
+
+    fn directive(&mut self, directive: &str, operands: &[&str]) -> Result<(), String> {
+        match directive {
+            ".text" | ".data" | ".ktext" | ".kdata" => {
+                // labels right before a section change belong to the old section
+                self.place_labels()?;
+                self.section = match directive {
+                    ".text" => SectionKind::Text,
+                    ".data" => SectionKind::Data,
+                    ".ktext" => SectionKind::KText,
+                    _ => SectionKind::KData,
+                };
+                match operands {
+                    [] => {}
+                    [addr] => {
+                        let Some(addr) = parse_int(addr) else {
+                            bail!("expected an address, found `{}`", addr);
+                        };
+                        let section = self.section();
+                        if section.bytes.is_empty() {
+                            section.base = addr as u32 as usize;
+                        } else if section.addr() != addr as u32 as usize {
+                            bail!("{} may only be moved before anything is placed in it", directive);
+                        }
+                    }
+                    _ => bail!("usage: {} [address]", directive),
+                }
+            }
+            ".globl" | ".global" => {}
+            // accepted so that sources for the gnu assembler work
+            ".set" => {}
+            ".align" => {
+                let [n] = operands else {
+                    bail!("usage: .align n");
+                };
+                let n = match parse_int(n) {
+                    Some(n @ 0..=3) => n,
+                    _ => bail!("alignment must be between 0 and 3, found `{}`", n),
+                };
+                self.section().align(1 << n);
+            }
+            ".space" => {
+                let [n] = operands else {
+                    bail!("usage: .space n");
+                };
+                let Some(n @ 0..) = parse_int(n) else {
+                    bail!("expected a size, found `{}`", n);
+                };
+                self.data_section(directive)?;
+                self.place_labels()?;
+                let section = self.section();
+                section.bytes.resize(section.bytes.len() + n as usize, 0);
+            }
+            ".byte" | ".half" | ".word" => {
+                let size = match directive {
+                    ".byte" => 1,
+                    ".half" => 2,
+                    _ => 4,
+                };
+                self.data_section(directive)?;
+                self.section().align(size);
+                self.place_labels()?;
+                for operand in operands {
+                    let (value, count) = split_repeat(operand)?;
+                    let expr = parse_expr(value)?;
+                    for _ in 0..count {
+                        let addr = self.section().addr();
+                        let n = match expr {
+                            Expr::Int(n) => n,
+                            Expr::Label(..) => {
+                                self.fixups.push(Fixup {
+                                    line: self.line,
+                                    section: self.section,
+                                    addr,
+                                    size,
+                                    expr: expr.clone(),
+                                });
+                                0
+                            }
+                        };
+                        self.section()
+                            .bytes
+                            .extend_from_slice(&(n as u32).to_le_bytes()[..size]);
+                    }
+                }
+            }
+            ".float" | ".double" => {
+                let size = if directive == ".float" { 4 } else { 8 };
+                self.data_section(directive)?;
+                self.section().align(size);
+                self.place_labels()?;
+                for operand in operands {
+                    let (value, count) = split_repeat(operand)?;
+                    let Some(value) = parse_float(value) else {
+                        bail!("expected a number, found `{}`", value);
+                    };
+                    for _ in 0..count {
+                        if directive == ".float" {
+                            let bytes = (value as f32).to_le_bytes();
+                            self.section().bytes.extend_from_slice(&bytes);
+                        } else {
+                            self.section().bytes.extend_from_slice(&value.to_le_bytes());
+                        }
+                    }
+                }
+            }
+            ".ascii" | ".asciiz" => {
+                self.data_section(directive)?;
+                self.place_labels()?;
+                for operand in operands {
+                    let mut s = parse_string(operand)?;
+                    if directive == ".asciiz" {
+                        s.push(0);
+                    }
+                    self.section().bytes.extend(s);
+                }
+            }
+            _ => bail!("unknown directive `{}`", directive),
+        }
+        Ok(())
+    }
+
+    fn data_section(&self, directive: &str) -> Result<(), String> {
+        if self.section.is_text() {
+            bail!("{} may not be used in .text", directive);
+        }
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        source: &str,
+        mnemonic: String,
+        operands: Vec<Operand>,
+    ) -> Result<(), String> {
+        if !self.section.is_text() {
+            bail!("instructions may only be placed in .text");
+        }
+        self.section().align(4);
+        self.place_labels()?;
+        let addr = self.section().addr();
+        let len = self.expand(&mnemonic, &operands)?.len();
+        let section = self.section();
+        section.bytes.resize(section.bytes.len() + len * 4, 0);
+        self.statements.push(Statement {
+            line: self.line,
+            source: source.to_string(),
+            section: self.section,
+            addr,
+            len,
+            mnemonic,
+            operands,
+        });
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Assembled, AsmError> {
+        self.place_labels()
+            .map_err(|msg| AsmError { line: self.line, msg })?;
+
+        self.resolved = true;
+        let mut lines = Vec::with_capacity(self.statements.len());
+        for stmt in &self.statements {
+            let err = |msg| AsmError {
+                line: stmt.line,
+                msg,
+            };
+            let insts = self.expand(&stmt.mnemonic, &stmt.operands).map_err(err)?;
+            if insts.len() != stmt.len {
+                return Err(err(format!(
+                    "`{}` changed size between passes",
+                    stmt.source
+                )));
+            }
+            for (i, (m, ops)) in insts.iter().enumerate() {
+                let addr = stmt.addr + i * 4;
+                let word = self.encode(m, ops, addr).map_err(err)?.opcode.0;
+                let section = match stmt.section {
+                    SectionKind::KText => &mut self.ktext,
+                    _ => &mut self.text,
+                };
+                section.bytes[addr - section.base..][..4].copy_from_slice(&word.to_le_bytes());
+            }
+            lines.push(SourceLine {
+                line: stmt.line,
+                text: stmt.source.clone(),
+                addrs: stmt.addr..stmt.addr + stmt.len * 4,
+            });
+        }
+
+        for fixup in &self.fixups {
+            let value = self.value(&fixup.expr).map_err(|msg| AsmError {
+                line: fixup.line,
+                msg,
+            })?;
+            let section = match fixup.section {
+                SectionKind::KData => &mut self.kdata,
+                _ => &mut self.data,
+            };
+            section.bytes[fixup.addr - section.base..][..fixup.size]
+                .copy_from_slice(&(value as u32).to_le_bytes()[..fixup.size]);
+        }
+
+        Ok(Assembled {
+            text: self.text,
+            data: self.data,
+            ktext: self.ktext,
+            kdata: self.kdata,
+            labels: self.labels,
+            lines,
+        })
+    }
+
+    fn value(&self, expr: &Expr) -> Result<i64, String> {
+        match expr {
+            Expr::Int(n) => Ok(*n),
+            Expr::Label(label, offset) => match self.labels.get(label) {
+                Some(addr) => Ok(*addr as i64 + offset),
+                None if !self.resolved => Ok(0),
+                None => bail!("unknown label `{}`", label),
+            },
+        }
+    }
+
+    /// The 16 bit offset from the delay slot of the branch at `addr`, plain integers are used
+    /// as the offset itself
+    fn branch(&self, expr: &Expr, addr: usize) -> Result<i16, String> {
+        let offset = match expr {
+            Expr::Int(n) => *n,
+            Expr::Label(..) => {
+                let offset = self.value(expr)? - (addr as i64 + 4);
+                if offset % 4 != 0 {
+                    bail!("branch target is not word aligned");
+                }
+                offset / 4
+            }
+        };
+        if !(i16::MIN as i64..=i16::MAX as i64).contains(&offset) {
+            bail!("branch target is too far away");
+        }
+        Ok(offset as i16)
+    }
+
+    /// The 26 bit instruction index of a jump target
+    fn jump(&self, expr: &Expr, addr: usize) -> Result<u32, String> {
+        let target = self.value(expr)? as u32;
+        if !target.is_multiple_of(4) {
+            bail!("jump target is not word aligned");
+        }
+        if target & 0xf000_0000 != (addr as u32 + 4) & 0xf000_0000 {
+            bail!("jump target is outside of the current 256 MiB region");
+        }
+        Ok((target >> 2) & 0x03ff_ffff)
+    }
+
+    /// The code of a `break` or trap, which is 10 bits
+    fn code(&self, expr: &Expr) -> Result<u16, String> {
+        let n = self.value(expr)?;
+        if !(0..1024).contains(&n) {
+            bail!("code must be between 0 and 1023, found {}", n);
+        }
+        Ok(n as u16)
+    }
+
+    fn simm16(&self, expr: &Expr) -> Result<i16, String> {
+        let n = self.value(expr)?;
+        if !(i16::MIN as i64..=i16::MAX as i64).contains(&n) {
+            bail!("{} does not fit in a signed 16 bit immediate", n);
+        }
+        Ok(n as i16)
+    }
+
+    /// An unsigned immediate, as the bits that end up in the instruction
+    fn uimm16(&self, expr: &Expr) -> Result<i16, String> {
+        let n = self.value(expr)?;
+        if !(0..=u16::MAX as i64).contains(&n) {
+            bail!("{} does not fit in an unsigned 16 bit immediate", n);
+        }
+        Ok(n as u16 as i16)
+    }
+
+    /// Expand pseudo-instructions into the real instructions that they are made of, following
+    /// the expansions that MARS uses.  Real instructions are returned as they are.
+    fn expand(&self, m: &str, ops: &[Operand]) -> Result<Vec<(String, Vec<Operand>)>, String> {
+        use Operand::{Expr as E, FReg as F, Mem as M, Reg as R};
+
+        let at = R(AT as u8);
+        let zero = R(0);
+        let int = |n: i64| E(Expr::Int(n));
+        macro_rules! inst {
+            ($m: expr $(, $op: expr)*$(,)?) => {
+                ($m.to_string(), vec![$($op.clone()),*])
+            };
+        }
+
+        let mut out = Vec::new();
+        match (m, ops) {
+            ("move", [rd @ R(_), rs @ R(_)]) => out.push(inst!("addu", rd, zero, rs)),
+            ("li" | "la", [rt @ R(_), E(imm)]) => self.load_imm(&mut out, rt, imm)?,
+            ("la", [rt @ R(_), M(offset, base)]) => match offset {
+                Expr::Int(n) if fits_i16(*n) => out.push(inst!("addi", rt, R(*base), int(*n))),
+                _ => {
+                    self.load_imm(&mut out, &at, offset)?;
+                    out.push(inst!("add", rt, R(*base), at));
+                }
+            },
+            ("neg" | "negu", [rd @ R(_), rs @ R(_)]) => {
+                let sub = if m == "neg" { "sub" } else { "subu" };
+                out.push(inst!(sub, rd, zero, rs));
+            }
+            ("not", [rd @ R(_), rs @ R(_)]) => out.push(inst!("nor", rd, rs, zero)),
+            ("abs", [rd @ R(_), rs @ R(_)]) => {
+                out.push(inst!("sra", at, rs, int(31)));
+                out.push(inst!("xor", rd, at, rs));
+                out.push(inst!("subu", rd, rd, at));
+            }
+            ("b", [label @ E(_)]) => out.push(inst!("bgez", zero, label)),
+            ("beqz" | "bnez", [rs @ R(_), label @ E(_)]) => {
+                out.push(inst!(&m[..3], rs, zero, label));
+            }
+            ("beq" | "bne", [rs @ R(_), E(imm), label @ E(_)]) => {
+                self.load_imm(&mut out, &at, imm)?;
+                out.push(inst!(m, rs, at, label));
+            }
+            (
+                "blt" | "bltu" | "bge" | "bgeu" | "bgt" | "bgtu" | "ble" | "bleu",
+                [rs @ R(_), rt, label @ E(_)],
+            ) => {
+                let unsigned = m.ends_with('u');
+                let slt = if unsigned { "sltu" } else { "slt" };
+                let branch = match &m[..3] {
+                    "blt" | "bgt" => "bne",
+                    _ => "beq",
+                };
+                match (&m[..3], rt) {
+                    ("blt" | "bge", R(_)) => out.push(inst!(slt, at, rs, rt)),
+                    ("blt" | "bge", E(Expr::Int(n))) if fits_i16(*n) => {
+                        let slti = if unsigned { "sltiu" } else { "slti" };
+                        out.push(inst!(slti, at, rs, rt));
+                    }
+                    ("blt" | "bge", E(imm)) => {
+                        self.load_imm(&mut out, &at, imm)?;
+                        out.push(inst!(slt, at, rs, at));
+                    }
+                    (_, R(_)) => out.push(inst!(slt, at, rt, rs)),
+                    (_, E(imm)) => {
+                        self.load_imm(&mut out, &at, imm)?;
+                        out.push(inst!(slt, at, at, rs));
+                    }
+                    (_, M(..) | F(_)) => bail!("usage: {} $rs, $rt, label", m),
+                }
+                out.push(inst!(branch, at, zero, label));
+            }
+            ("mul", [rd @ R(_), rs @ R(_), rt @ E(_)]) => {
+                let rt = self.reg_or_at(&mut out, rt)?;
+                out.push(inst!("mul", rd, rs, rt));
+            }
+            ("mulu", [rd @ R(_), rs @ R(_), rt]) => {
+                let rt = self.reg_or_at(&mut out, rt)?;
+                out.push(inst!("multu", rs, rt));
+                out.push(inst!("mflo", rd));
+            }
+            ("div" | "divu" | "rem" | "remu", [rd @ R(_), rs @ R(_), rt]) => {
+                // like MARS, a register divisor is checked first, skipping a `break` unless it
+                // is zero.  An immediate one is known not to be.
+                if let R(_) = rt {
+                    if self.delay_slots {
+                        // the `break` would always run in the delay slot
+                        out.push(inst!("bne", rt, zero, int(2)));
+                        out.push(inst!("nop"));
+                    } else {
+                        out.push(inst!("bne", rt, zero, int(1)));
+                    }
+                    out.push(inst!("break"));
+                }
+                let rt = self.reg_or_at(&mut out, rt)?;
+                let (div, mf) = match m {
+                    "div" => ("div", "mflo"),
+                    "divu" => ("divu", "mflo"),
+                    "rem" => ("div", "mfhi"),
+                    _ => ("divu", "mfhi"),
+                };
+                out.push(inst!(div, rs, rt));
+                out.push(inst!(mf, rd));
+            }
+            ("sgt" | "sgtu", [rd @ R(_), rs @ R(_), rt @ R(_)]) => {
+                let slt = if m == "sgt" { "slt" } else { "sltu" };
+                out.push(inst!(slt, rd, rt, rs));
+            }
+            ("sge" | "sgeu" | "sle" | "sleu", [rd @ R(_), rs @ R(_), rt @ R(_)]) => {
+                let slt = if m.ends_with('u') { "sltu" } else { "slt" };
+                if m.starts_with("sge") {
+                    out.push(inst!(slt, rd, rs, rt));
+                } else {
+                    out.push(inst!(slt, rd, rt, rs));
+                }
+                out.push(inst!("xori", rd, rd, int(1)));
+            }
+            ("seq" | "sne", [rd @ R(_), rs @ R(_), rt @ R(_)]) => {
+                out.push(inst!("subu", rd, rs, rt));
+                if m == "seq" {
+                    out.push(inst!("sltiu", rd, rd, int(1)));
+                } else {
+                    out.push(inst!("sltu", rd, zero, rd));
+                }
+            }
+            (
+                "add" | "addu" | "sub" | "subu" | "and" | "or" | "xor" | "nor" | "slt" | "sltu",
+                [rd @ R(_), rs @ R(_), E(imm)],
+            ) => {
+                let imm_form = match m {
+                    "add" => Some(("addi", true)),
+                    "addu" => Some(("addiu", true)),
+                    "and" => Some(("andi", false)),
+                    "or" => Some(("ori", false)),
+                    "xor" => Some(("xori", false)),
+                    "slt" => Some(("slti", true)),
+                    "sltu" => Some(("sltiu", true)),
+                    _ => None,
+                };
+                match (imm_form, imm) {
+                    (Some((i, true)), Expr::Int(n)) if fits_i16(*n) => {
+                        out.push(inst!(i, rd, rs, int(*n)))
+                    }
+                    (Some((i, false)), Expr::Int(n)) if fits_u16(*n) => {
+                        out.push(inst!(i, rd, rs, int(*n)))
+                    }
+                    _ => {
+                        self.load_imm(&mut out, &at, imm)?;
+                        out.push(inst!(m, rd, rs, at));
+                    }
+                }
+            }
+            (
+                "addi" | "addiu" | "slti" | "sltiu" | "andi" | "ori" | "xori",
+                [rt @ R(_), rs @ R(_), E(imm)],
+            ) => {
+                let signed = !matches!(m, "andi" | "ori" | "xori");
+                match imm {
+                    Expr::Int(n) if signed && fits_i16(*n) || !signed && fits_u16(*n) => {
+                        out.push(inst!(m, rt, rs, int(*n)));
+                    }
+                    _ => {
+                        self.load_imm(&mut out, &at, imm)?;
+                        let op = match m {
+                            "addi" => "add",
+                            "addiu" => "addu",
+                            "slti" => "slt",
+                            "sltiu" => "sltu",
+                            "andi" => "and",
+                            "ori" => "or",
+                            _ => "xor",
+                        };
+                        out.push(inst!(op, rt, rs, at));
+                    }
+                }
+            }
+            (
+                "lb" | "lbu" | "lh" | "lhu" | "lw" | "lwl" | "lwr" | "sb" | "sh" | "sw" | "swl"
+                | "swr" | "ll" | "sc",
+                [rt @ R(_), addr],
+            )
+            | (
+                "lwc1" | "ldc1" | "swc1" | "sdc1" | "l.s" | "l.d" | "s.s" | "s.d",
+                [rt @ F(_), addr],
+            ) => {
+                let m = match m {
+                    "l.s" => "lwc1",
+                    "l.d" => "ldc1",
+                    "s.s" => "swc1",
+                    "s.d" => "sdc1",
+                    m => m,
+                };
+                let (addr, base) = match addr {
+                    E(addr) => (addr, None),
+                    M(addr, base) => (addr, Some(*base)),
+                    R(_) | F(_) => bail!("usage: {} $rt, offset($base)", m),
+                };
+                match addr {
+                    Expr::Int(n) if fits_i16(*n) => {
+                        out.push(inst!(m, rt, M(Expr::Int(*n), base.unwrap_or(0))));
+                    }
+                    _ => {
+                        // the low half is sign extended by the load, so carry into the high half
+                        let value = self.value(addr)? as u32;
+                        let hi = value.wrapping_add(0x8000) >> 16;
+                        out.push(inst!("lui", at, int(hi as i64)));
+                        if let Some(base) = base {
+                            out.push(inst!("addu", at, at, R(base)));
+                        }
+                        let lo = value as u16 as i16 as i64;
+                        out.push(inst!(m, rt, M(Expr::Int(lo), AT as u8)));
+                    }
+                }
+            }
+            _ => out.push((m.to_string(), ops.to_vec())),
+        }
+        Ok(out)
+    }
+
+    /// Load a 32 bit immediate the way that `li` does, labels always use the full `lui`/`ori`
+    /// form since their value is not known when sizing
+    fn load_imm(
+        &self,
+        out: &mut Vec<(String, Vec<Operand>)>,
+        rt: &Operand,
+        imm: &Expr,
+    ) -> Result<(), String> {
+        let int = |n: i64| Operand::Expr(Expr::Int(n));
+        match imm {
+            Expr::Int(n) if fits_i16(*n) => {
+                out.push(("addiu".into(), vec![rt.clone(), Operand::Reg(0), int(*n)]));
+            }
+            Expr::Int(n) if fits_u16(*n) => {
+                out.push(("ori".into(), vec![rt.clone(), Operand::Reg(0), int(*n)]));
+            }
+            _ => {
+                let value = self.value(imm)?;
+                if !(i32::MIN as i64..=u32::MAX as i64).contains(&value) {
+                    bail!("{} does not fit in 32 bits", value);
+                }
+                let value = value as u32;
+                let at = Operand::Reg(AT as u8);
+                out.push(("lui".into(), vec![at.clone(), int((value >> 16) as i64)]));
+                out.push((
+                    "ori".into(),
+                    vec![rt.clone(), at, int((value & 0xffff) as i64)],
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers are used as they are, immediates are loaded into `$at`
+    fn reg_or_at(
+        &self,
+        out: &mut Vec<(String, Vec<Operand>)>,
+        op: &Operand,
+    ) -> Result<Operand, String> {
+        match op {
+            Operand::Reg(_) => Ok(op.clone()),
+            Operand::Expr(imm) => {
+                let at = Operand::Reg(AT as u8);
+                self.load_imm(out, &at, imm)?;
+                Ok(at)
+            }
+            Operand::Mem(..) | Operand::FReg(_) => bail!("expected a register or immediate"),
+        }
+    }
+
+    fn encode(&self, m: &str, ops: &[Operand], addr: usize) -> Result<Inst, String> {
+        use Operand::{Expr as E, FReg as F, Mem as M, Reg as R};
+
+        macro_rules! usage {
+            ($usage: literal) => {
+                bail!(concat!("usage: {} ", $usage), m)
+            };
+        }
+
+        if m == "nop" {
+            let [] = ops else { usage!("") };
+            return Ok(Inst::r_type(Func::Sll, 0, 0, 0, 0));
+        }
+
+        if let Some(func) = (0..64).filter_map(Func::new).find(|f| f.inst_name() == m) {
+            return Ok(match func {
+                Func::Sll | Func::Srl | Func::Sra => {
+                    let [R(rd), R(rt), E(sa)] = ops else {
+                        usage!("$rd, $rt, shamt")
+                    };
+                    let sa = self.value(sa)?;
+                    if !(0..32).contains(&sa) {
+                        bail!("shift amount must be between 0 and 31");
+                    }
+                    Inst::r_type(func, *rd, 0, *rt, sa as u8)
+                }
+                Func::Sllv | Func::Srlv | Func::Srav => {
+                    let [R(rd), R(rt), R(rs)] = ops else {
+                        usage!("$rd, $rt, $rs")
+                    };
+                    Inst::r_type(func, *rd, *rs, *rt, 0)
+                }
+                Func::Jr => {
+                    let [R(rs)] = ops else { usage!("$rs") };
+                    Inst::r_type(func, 0, *rs, 0, 0)
+                }
+                Func::Jalr => match ops {
+                    [R(rs)] => Inst::r_type(func, RA as u8, *rs, 0, 0),
+                    [R(rd), R(rs)] => Inst::r_type(func, *rd, *rs, 0, 0),
+                    _ => usage!("[$rd,] $rs"),
+                },
+                Func::Syscall => {
+                    let [] = ops else { usage!("") };
+                    Inst::r_type(func, 0, 0, 0, 0)
+                }
+                Func::Sync => match ops {
+                    [] => Inst::r_type(func, 0, 0, 0, 0),
+                    [E(stype)] => {
+                        let stype = self.value(stype)?;
+                        if !(0..32).contains(&stype) {
+                            bail!("sync type must be between 0 and 31");
+                        }
+                        Inst::r_type(func, 0, 0, 0, stype as u8)
+                    }
+                    _ => usage!("[stype]"),
+                },
+                Func::Break => {
+                    // like gas, one code goes in the upper half of the 20 bit field
+                    let (code, subcode) = match ops {
+                        [] => (0, 0),
+                        [E(code)] => (self.code(code)?, 0),
+                        [E(code), E(subcode)] => (self.code(code)?, self.code(subcode)?),
+                        _ => usage!("[code[, code]]"),
+                    };
+                    let (hi, lo) = (split_code(code), split_code(subcode));
+                    Inst::r_type(func, lo.0, hi.0, hi.1, lo.1)
+                }
+                Func::Tge | Func::Tgeu | Func::Tlt | Func::Tltu | Func::Teq | Func::Tne => {
+                    let (rs, rt, code) = match ops {
+                        [R(rs), R(rt)] => (*rs, *rt, 0),
+                        [R(rs), R(rt), E(code)] => (*rs, *rt, self.code(code)?),
+                        _ => usage!("$rs, $rt[, code]"),
+                    };
+                    let (rd, shamt) = split_code(code);
+                    Inst::r_type(func, rd, rs, rt, shamt)
+                }
+                Func::Mfhi | Func::Mflo => {
+                    let [R(rd)] = ops else { usage!("$rd") };
+                    Inst::r_type(func, *rd, 0, 0, 0)
+                }
+                Func::Mthi | Func::Mtlo => {
+                    let [R(rs)] = ops else { usage!("$rs") };
+                    Inst::r_type(func, 0, *rs, 0, 0)
+                }
+                Func::Mult | Func::MultU | Func::Div | Func::DivU => {
+                    let [R(rs), R(rt)] = ops else {
+                        usage!("$rs, $rt")
+                    };
+                    Inst::r_type(func, 0, *rs, *rt, 0)
+                }
+                Func::Add
+                | Func::Addu
+                | Func::Sub
+                | Func::Subu
+                | Func::And
+                | Func::Or
+                | Func::Xor
+                | Func::Nor
+                | Func::Slt
+                | Func::Sltu
+                | Func::Movz
+                | Func::Movn => {
+                    let [R(rd), R(rs), R(rt)] = ops else {
+                        usage!("$rd, $rs, $rt")
+                    };
+                    Inst::r_type(func, *rd, *rs, *rt, 0)
+                }
+            });
+        }
+
+        if let Some(inst) = self.encode_cop1(m, ops, addr)? {
+            return Ok(inst);
+        }
+
+        match m {
+            "mfc0" | "mtc0" => {
+                let [R(rt), R(rd)] = ops else {
+                    usage!("$rt, $rd")
+                };
+                let op = if m == "mfc0" { Cop0Op::Mf } else { Cop0Op::Mt };
+                return Ok(Inst::cop0_move(op, *rt, *rd));
+            }
+            "eret" => {
+                let [] = ops else { usage!("") };
+                return Ok(Inst::eret());
+            }
+            "ext" | "ins" => {
+                let [R(rt), R(rs), E(pos), E(size)] = ops else {
+                    usage!("$rt, $rs, pos, size")
+                };
+                let (pos, size) = (self.value(pos)?, self.value(size)?);
+                if !(0..32).contains(&pos) || size < 1 || pos + size > 32 {
+                    bail!("the field must be inside of a word, found pos {} size {}", pos, size);
+                }
+                let func = if m == "ext" { Special3::Ext } else { Special3::Ins };
+                return Ok(Inst::bit_field_type(func, *rt, *rs, pos as u8, size as u8));
+            }
+            _ => {}
+        }
+
+        if let Some(func) = (0..64).filter_map(Special2::new).find(|f| f.inst_name() == m) {
+            return Ok(match func {
+                Special2::Mul => {
+                    let [R(rd), R(rs), R(rt)] = ops else {
+                        usage!("$rd, $rs, $rt")
+                    };
+                    Inst::special2_type(func, *rd, *rs, *rt)
+                }
+                Special2::Madd | Special2::MaddU | Special2::Msub | Special2::MsubU => {
+                    let [R(rs), R(rt)] = ops else {
+                        usage!("$rs, $rt")
+                    };
+                    Inst::special2_type(func, 0, *rs, *rt)
+                }
+                Special2::Clz | Special2::Clo => {
+                    let [R(rd), R(rs)] = ops else {
+                        usage!("$rd, $rs")
+                    };
+                    // MIPS32 requires `rt` to be a copy of `rd`
+                    Inst::special2_type(func, *rd, *rs, *rd)
+                }
+            });
+        }
+
+        if let Some(op) = (0..32).filter_map(Bshfl::new).find(|b| b.inst_name() == m) {
+            let [R(rd), R(rt)] = ops else {
+                usage!("$rd, $rt")
+            };
+            return Ok(Inst::bshfl_type(op, *rd, *rt));
+        }
+
+        if m == "bal" {
+            let [E(label)] = ops else { usage!("label") };
+            return Ok(Inst::regimm_type(RegImm::Bgezal, 0, self.branch(label, addr)?));
+        }
+
+        if let Some(op) = (0..32).filter_map(RegImm::new).find(|r| r.inst_name() == m) {
+            if op.is_trap() {
+                let [R(rs), E(imm)] = ops else {
+                    usage!("$rs, imm")
+                };
+                return Ok(Inst::regimm_type(op, *rs, self.simm16(imm)?));
+            }
+            let [R(rs), E(label)] = ops else {
+                usage!("$rs, label")
+            };
+            return Ok(Inst::regimm_type(op, *rs, self.branch(label, addr)?));
+        }
+
+        let Some(kind) = (2..64)
+            .filter_map(InstKind::new)
+            .find(|k| k.inst_name() == m)
+        else {
+            bail!("unknown instruction `{}`", m);
+        };
+        Ok(match kind {
+            InstKind::Special | InstKind::Special2 | InstKind::Special3 => {
+                unreachable!("special instructions are matched by func")
+            }
+            InstKind::AddI | InstKind::AddIU | InstKind::SltI | InstKind::SltIU => {
+                let [R(rt), R(rs), E(imm)] = ops else {
+                    usage!("$rt, $rs, imm")
+                };
+                Inst::i_type(kind, *rt, *rs, self.simm16(imm)?)
+            }
+            InstKind::AndI | InstKind::OrI | InstKind::XorI => {
+                let [R(rt), R(rs), E(imm)] = ops else {
+                    usage!("$rt, $rs, imm")
+                };
+                Inst::i_type(kind, *rt, *rs, self.uimm16(imm)?)
+            }
+            InstKind::LUI => {
+                let [R(rt), E(imm)] = ops else {
+                    usage!("$rt, imm")
+                };
+                let imm = self
+                    .uimm16(imm)
+                    .or_else(|_| self.simm16(imm))
+                    .map_err(|_| "lui takes a 16 bit immediate".to_string())?;
+                Inst::i_type(kind, *rt, 0, imm)
+            }
+            InstKind::Beq | InstKind::Bne => {
+                let [R(rs), R(rt), E(label)] = ops else {
+                    usage!("$rs, $rt, label")
+                };
+                Inst::i_type(kind, *rt, *rs, self.branch(label, addr)?)
+            }
+            InstKind::Blez | InstKind::Bgtz => {
+                let [R(rs), E(label)] = ops else {
+                    usage!("$rs, label")
+                };
+                Inst::i_type(kind, 0, *rs, self.branch(label, addr)?)
+            }
+            InstKind::RegImm => unreachable!("regimm instructions are matched by rt"),
+            InstKind::J | InstKind::Jal => {
+                let [E(label)] = ops else { usage!("label") };
+                Inst::j_type(kind, self.jump(label, addr)?)
+            }
+            InstKind::LB
+            | InstKind::LH
+            | InstKind::LWL
+            | InstKind::LW
+            | InstKind::LBU
+            | InstKind::LHU
+            | InstKind::LWR
+            | InstKind::SB
+            | InstKind::SH
+            | InstKind::SWL
+            | InstKind::SW
+            | InstKind::SWR
+            | InstKind::LL
+            | InstKind::Sc => {
+                let [R(rt), M(offset, base)] = ops else {
+                    usage!("$rt, offset($base)")
+                };
+                Inst::i_type(kind, *rt, *base, self.simm16(offset)?)
+            }
+            InstKind::Lwc1 | InstKind::Ldc1 | InstKind::Swc1 | InstKind::Sdc1 => {
+                let [F(ft), M(offset, base)] = ops else {
+                    usage!("$ft, offset($base)")
+                };
+                if matches!(kind, InstKind::Ldc1 | InstKind::Sdc1) {
+                    even(*ft)?;
+                }
+                Inst::i_type(kind, *ft, *base, self.simm16(offset)?)
+            }
+            InstKind::Cache => {
+                let [E(op), M(offset, base)] = ops else {
+                    usage!("op, offset($base)")
+                };
+                let op = self.value(op)?;
+                if !(0..32).contains(&op) {
+                    bail!("cache op must be between 0 and 31");
+                }
+                Inst::i_type(kind, op as u8, *base, self.simm16(offset)?)
+            }
+            InstKind::Cop0 => unreachable!("cop0 instructions are matched by name"),
+            InstKind::Cop1 => unreachable!("cop1 instructions are matched by format"),
+        })
+    }
+
+    /// Encode the coprocessor 1 instructions, other than loads and stores, which are named by
+    /// their operation and format (`add.s`, `cvt.d.w`), returning `None` for anything else
+    fn encode_cop1(&self, m: &str, ops: &[Operand], addr: usize) -> Result<Option<Inst>, String> {
+        use Operand::{Expr as E, FReg as F, Reg as R};
+
+        macro_rules! usage {
+            ($usage: literal) => {
+                bail!(concat!("usage: {} ", $usage), m)
+            };
+        }
+
+        let inst = match m {
+            "mfc1" | "mtc1" => {
+                let [R(rt), F(fs)] = ops else {
+                    usage!("$rt, $fs")
+                };
+                let fmt = if m == "mfc1" { Fmt::Mf } else { Fmt::Mt };
+                Inst::fpu_move(fmt, *rt, *fs)
+            }
+            "bc1t" | "bc1f" | "bc1tl" | "bc1fl" => {
+                let (cc, label) = match ops {
+                    [E(label)] => (0, label),
+                    [E(cc), E(label)] => (self.cc(cc)?, label),
+                    _ => usage!("[cc,] label"),
+                };
+                let offset = self.branch(label, addr)?;
+                Inst::fpu_branch(cc, m.ends_with('l'), m.starts_with("bc1t"), offset)
+            }
+            _ => {
+                let Some((name, fmt)) = m.rsplit_once('.') else {
+                    return Ok(None);
+                };
+                let fmt = match fmt {
+                    "s" => Fmt::S,
+                    "d" => Fmt::D,
+                    "w" => Fmt::W,
+                    _ => return Ok(None),
+                };
+                let Some(func) = (0..64)
+                    .filter_map(FpuFunc::new)
+                    .find(|f| f.inst_name() == name)
+                else {
+                    return Ok(None);
+                };
+                if !func.formats().contains(&fmt) {
+                    bail!("unknown instruction `{}`", m);
+                }
+                // doubles are held in even/odd pairs
+                let check = |fmt: Fmt, r: u8| match fmt {
+                    Fmt::D => even(r),
+                    _ => Ok(()),
+                };
+                if func.is_compare() {
+                    let (cc, fs, ft) = match ops {
+                        [F(fs), F(ft)] => (0, fs, ft),
+                        [E(cc), F(fs), F(ft)] => (self.cc(cc)?, fs, ft),
+                        _ => usage!("[cc,] $fs, $ft"),
+                    };
+                    check(fmt, *fs)?;
+                    check(fmt, *ft)?;
+                    Inst::fpu_type(func, fmt, cc << 2, *fs, *ft)
+                } else if func.is_unary() {
+                    let [F(fd), F(fs)] = ops else {
+                        usage!("$fd, $fs")
+                    };
+                    check(func.result(fmt).unwrap_or(fmt), *fd)?;
+                    check(fmt, *fs)?;
+                    Inst::fpu_type(func, fmt, *fd, *fs, 0)
+                } else {
+                    let [F(fd), F(fs), F(ft)] = ops else {
+                        usage!("$fd, $fs, $ft")
+                    };
+                    check(fmt, *fd)?;
+                    check(fmt, *fs)?;
+                    check(fmt, *ft)?;
+                    Inst::fpu_type(func, fmt, *fd, *fs, *ft)
+                }
+            }
+        };
+        Ok(Some(inst))
+    }
+
+    /// A floating point condition flag
+    fn cc(&self, expr: &Expr) -> Result<u8, String> {
+        match self.value(expr)? {
+            cc @ 0..8 => Ok(cc as u8),
+            cc => bail!("condition flag must be between 0 and 7, found {}", cc),
+        }
+    }
+}
+
+fn even(r: u8) -> Result<(), String> {
+    if !r.is_multiple_of(2) {
+        bail!("double precision uses even registers, found `$f{}`", r);
+    }
+    Ok(())
+}
+
+/// Split a 10 bit code into the two 5 bit register fields that hold it
+fn split_code(code: u16) -> (u8, u8) {
+    ((code >> 5) as u8, (code & 0x1f) as u8)
+}
+
+fn fits_i16(n: i64) -> bool {
+    (i16::MIN as i64..=i16::MAX as i64).contains(&n)
+}
+
+fn fits_u16(n: i64) -> bool {
+    (0..=u16::MAX as i64).contains(&n)
+}
+
+/// Call `f` for each character that is not inside of a string or character literal, stopping
+/// once it returns `true`, returning the index of that character
+fn find_unquoted(s: &str, mut f: impl FnMut(char) -> bool) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if f(c) => return Some(i),
+            None => {}
+        }
+    }
+    None
+}
+
+fn strip_comment(line: &str) -> &str {
+    match find_unquoted(line, |c| c == '#') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let i = find_unquoted(line, |c| c == ':')?;
+    let label = line[..i].trim();
+    is_ident(label).then(|| (label, &line[i + 1..]))
+}
+
+fn split_operands(s: &str) -> Result<Vec<&str>, String> {
+    let mut operands = Vec::new();
+    let mut s = s.trim();
+    if s.is_empty() {
+        return Ok(operands);
+    }
+    while let Some(i) = find_unquoted(s, |c| c == ',') {
+        operands.push(s[..i].trim());
+        s = &s[i + 1..];
+    }
+    operands.push(s.trim());
+    if operands.iter().any(|o| o.is_empty()) {
+        bail!("empty operand");
+    }
+    Ok(operands)
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+fn parse_reg(s: &str) -> Result<u8, String> {
+    let Some(name) = s.strip_prefix('$') else {
+        bail!("expected a register, found `{}`", s);
+    };
+    let n = match name.parse::<u8>() {
+        Ok(n @ 0..32) => Some(n as usize),
+        Ok(_) => None,
+        Err(_) if name == "s8" => Some(30),
+        Err(_) => REGS.iter().position(|r| &r[1..] == name),
+    };
+    match n {
+        Some(n) => Ok(n as u8),
+        None => bail!("unknown register `{}`", s),
+    }
+}
+
+fn parse_operand(s: &str) -> Result<Operand, String> {
+    if s.starts_with('\'') {
+        return parse_expr(s).map(Operand::Expr);
+    }
+    if let Some(mem) = s.strip_suffix(')') {
+        let Some((offset, base)) = mem.split_once('(') else {
+            bail!("unmatched `)` in `{}`", s);
+        };
+        let offset = match offset.trim() {
+            "" => Expr::Int(0),
+            offset => parse_expr(offset)?,
+        };
+        return Ok(Operand::Mem(offset, parse_reg(base.trim())?));
+    }
+    if let Some(n) = s.strip_prefix("$f").and_then(|n| n.parse::<u8>().ok()) {
+        if n >= 32 {
+            bail!("unknown register `{}`", s);
+        }
+        return Ok(Operand::FReg(n));
+    }
+    if s.starts_with('$') {
+        return parse_reg(s).map(Operand::Reg);
+    }
+    parse_expr(s).map(Operand::Expr)
+}
+
+fn parse_expr(s: &str) -> Result<Expr, String> {
+    if let Some(n) = parse_int(s) {
+        return Ok(Expr::Int(n));
+    }
+    let (label, offset) = match s.rfind(['+', '-']) {
+        Some(i) if i > 0 => match parse_int(&s[i..]) {
+            Some(offset) => (s[..i].trim(), offset),
+            None => bail!("invalid offset in `{}`", s),
+        },
+        _ => (s, 0),
+    };
+    if !is_ident(label) {
+        bail!("expected a label or number, found `{}`", s);
+    }
+    Ok(Expr::Label(label.to_string(), offset))
+}
+
+fn parse_int(s: &str) -> Option<i64> {
+    if s.starts_with('\'') {
+        let c = parse_quoted(s, '\'').ok()?;
+        let [c] = c[..] else {
+            return None;
+        };
+        return Some(c as i64);
+    }
+    let (neg, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let n = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i64::from_str_radix(bin, 2)
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse()
+    } else {
+        return None;
+    };
+    n.ok().map(|n| if neg { -n } else { n })
+}
+
+fn parse_float(s: &str) -> Option<f64> {
+    match parse_int(s) {
+        Some(n) => Some(n as f64),
+        None => s.parse().ok(),
+    }
+}
+
+/// MARS allows `value : count` to repeat a value in data directives
+fn split_repeat(operand: &str) -> Result<(&str, i64), String> {
+    match operand.rsplit_once(':') {
+        Some((value, count)) if !value.ends_with('\'') => {
+            let Some(count @ 0..) = parse_int(count.trim()) else {
+                bail!("expected a repeat count, found `{}`", count);
+            };
+            Ok((value.trim(), count))
+        }
+        _ => Ok((operand, 1)),
+    }
+}
+
+fn parse_string(s: &str) -> Result<Vec<u8>, String> {
+    parse_quoted(s, '"')
+}
+
+fn parse_quoted(s: &str, quote: char) -> Result<Vec<u8>, String> {
+    let Some(inner) = s
+        .strip_prefix(quote)
+        .and_then(|s| s.strip_suffix(quote))
+    else {
+        bail!("expected a {}quoted literal, found `{}`", quote, s);
+    };
+    let mut out = Vec::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '\'' | '"')) => c,
+                Some(c) => bail!("unknown escape `\\{}`", c),
+                None => bail!("unterminated escape in `{}`", s),
+            }
+        } else {
+            c
+        };
+        let mut buf = [0; 4];
+        out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cop0::Fault, inst::Opcode, Greg, InstructionResult};
+
+    fn asm(src: &str) -> Assembled {
+        assemble(src, &MemoryMap::MARS, false).unwrap()
+    }
+
+    fn error(src: &str) -> AsmError {
+        assemble(src, &MemoryMap::MARS, false).unwrap_err()
+    }
+
+    fn words(section: &Section) -> Vec<u32> {
+        section
+            .bytes
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn data_directives() {
+        let asm = asm(r#"
+            .globl main
+            .data
+            bytes: .byte 1, 2
+            words: .word 3, bytes
+            .half 4
+            str: .asciiz "hi"
+            .ascii "!"
+            .space 3
+            .align 2
+            end: .word 5 : 2
+        "#);
+        let data = 0x1001_0000;
+        assert_eq!(asm.labels["bytes"], data);
+        // .word aligns itself, and the label with it
+        assert_eq!(asm.labels["words"], data + 4);
+        assert_eq!(asm.labels["str"], data + 14);
+        assert_eq!(asm.labels["end"], data + 24);
+        assert_eq!(
+            asm.data.bytes,
+            [
+                [1, 2, 0, 0].as_slice(),
+                &3u32.to_le_bytes(),
+                &(data as u32).to_le_bytes(),
+                &[4, 0],
+                b"hi\0!",
+                &[0; 3],
+                &[0; 3],
+                &5u32.to_le_bytes(),
+                &5u32.to_le_bytes(),
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn instructions() {
+        let asm = asm("
+            main: addu $t0, $t1, $t2
+            lw $t0, 4($sp)
+            loop: beq $t0, $zero, main
+            j loop
+            jal main
+        ");
+        let (main, l) = (0x0040_0000, 0x0040_0008);
+        assert_eq!(asm.labels["main"], main);
+        assert_eq!(asm.labels["loop"], l);
+        let expected = [
+            Inst::r_type(Func::Addu, 8, 9, 10, 0),
+            Inst::i_type(InstKind::LW, 8, 29, 4),
+            // from the delay slot back to main
+            Inst::i_type(InstKind::Beq, 0, 8, -3),
+            Inst::j_type(InstKind::J, l as u32 >> 2),
+            Inst::j_type(InstKind::Jal, main as u32 >> 2),
+        ];
+        let expected = expected.map(|inst| inst.opcode.0);
+        assert_eq!(words(&asm.text), expected);
+        assert_eq!(asm.entry(), main);
+        // and decodes back to the instruction it was assembled from
+        let names = expected.map(|word| Inst::new(Opcode(word)).unwrap().inst_name());
+        assert_eq!(names, ["addu", "lw", "beq", "j", "jal"]);
+    }
+
+    #[test]
+    fn kernel_sections() {
+        let asm = asm("
+            .text
+            nop
+            .ktext 0x80000180
+            handler: eret
+            .kdata
+            saved: .word 0
+            .data
+            x: .word 1
+        ");
+        assert_eq!(asm.ktext.base, 0x8000_0180);
+        assert_eq!(words(&asm.ktext), [Inst::eret().opcode.0]);
+        assert_eq!(asm.labels["handler"], 0x8000_0180);
+        assert_eq!(asm.labels["saved"], 0x9000_0000);
+        assert_eq!(asm.labels["x"], 0x1001_0000);
+        assert_eq!(words(&asm.text), [0]);
+    }
+
+    #[test]
+    fn errors() {
+        let e = error(".text\nnop\nfrob $t0\n");
+        assert_eq!((e.line, e.msg.as_str()), (3, "unknown instruction `frob`"));
+        let e = error("a: nop\na: nop\n");
+        assert_eq!(
+            (e.line, e.msg.as_str()),
+            (2, "label `a` is defined more than once")
+        );
+        // unknown labels are only found once everything has been read
+        let e = error("j nowhere\nnop\n");
+        assert_eq!((e.line, e.msg.as_str()), (1, "unknown label `nowhere`"));
+        let e = error(".data\n.word 1\naddu $t0, $t0, $t0\n");
+        assert_eq!(e.line, 3);
+        assert_eq!(
+            e.to_string(),
+            "line 3: instructions may only be placed in .text"
+        );
+    }
+
+    /// The words that one line of `.text` assembles into
+    fn expand(line: &str) -> Vec<u32> {
+        let src = format!(
+            ".data\n.space 0x8000\nx: .word 0\n.text\n{}\nlabel:\n",
+            line
+        );
+        words(&asm(&src).text)
+    }
+
+    fn i(kind: InstKind, rt: usize, rs: usize, imm: i64) -> u32 {
+        Inst::i_type(kind, rt as u8, rs as u8, imm as i16).opcode.0
+    }
+
+    fn r(func: Func, rd: usize, rs: usize, rt: usize) -> u32 {
+        Inst::r_type(func, rd as u8, rs as u8, rt as u8, 0).opcode.0
+    }
+
+    #[test]
+    fn immediate_sizes() {
+        use InstKind::*;
+        let (t0, t1) = (8, 9);
+        // `li` uses one instruction for a signed or unsigned 16 bit immediate, two otherwise
+        assert_eq!(expand("li $t0, -100"), [i(AddIU, t0, 0, -100)]);
+        assert_eq!(expand("li $t0, 0xffff"), [i(OrI, t0, 0, 0xffff)]);
+        let big = [i(LUI, AT, 0, 0x1234), i(OrI, t0, AT, 0x5678)];
+        assert_eq!(expand("li $t0, 0x12345678"), big);
+        // a label is always loaded in full, its value isn't known when sizing
+        let x = [i(LUI, AT, 0, 0x1001), i(OrI, t0, AT, 0x8000)];
+        assert_eq!(expand("la $t0, x"), x);
+
+        // arithmetic takes a signed immediate, logic an unsigned one, anything else goes
+        // through $at
+        assert_eq!(expand("addi $t0, $t1, -5"), [i(AddI, t0, t1, -5)]);
+        let addi = [i(OrI, AT, 0, 40000), r(Func::Add, t0, t1, AT)];
+        assert_eq!(expand("addi $t0, $t1, 40000"), addi);
+        assert_eq!(expand("andi $t0, $t1, 40000"), [i(AndI, t0, t1, 40000)]);
+        let andi = [i(AddIU, AT, 0, -1), r(Func::And, t0, t1, AT)];
+        assert_eq!(expand("andi $t0, $t1, -1"), andi);
+        let add = [
+            i(LUI, AT, 0, 1),
+            i(OrI, AT, AT, 0),
+            r(Func::Add, t0, t1, AT),
+        ];
+        assert_eq!(expand("add $t0, $t1, 0x10000"), add);
+
+        // branches compare with an immediate in $at, `label` is right after the branch
+        let beq = i(Beq, AT, t0, 0);
+        assert_eq!(expand("beq $t0, 5, label"), [i(AddIU, AT, 0, 5), beq]);
+        let far = [i(LUI, AT, 0, 1), i(OrI, AT, AT, 0x2345), beq];
+        assert_eq!(expand("beq $t0, 0x12345, label"), far);
+
+        // a load from a label or a large offset adds the high half in $at, carrying for the
+        // sign extended low half
+        assert_eq!(expand("lw $t0, 100($t1)"), [i(LW, t0, t1, 100)]);
+        let lw = [i(LUI, AT, 0, 0x1002), i(LW, t0, AT, -0x8000)];
+        assert_eq!(expand("lw $t0, x"), lw);
+        let lw = [
+            i(LUI, AT, 0, 1),
+            r(Func::Addu, AT, AT, t1),
+            i(LW, t0, AT, 0x2345),
+        ];
+        assert_eq!(expand("lw $t0, 0x12345($t1)"), lw);
+    }
+
+    /// Run `src` until it stops, returning `$t0`
+    fn run(src: &str, delay_slots: bool) -> Result<u32, Fault> {
+        let mut greg = Greg::from_asm(src, &MemoryMap::MARS, delay_slots).unwrap();
+        while let InstructionResult::None = greg.step()? {}
+        Ok(greg.reg[8])
+    }
+
+    #[test]
+    fn divide_checks_for_zero() {
+        for delay_slots in [false, true] {
+            let div = "li $t1, 7\nli $t2, 2\ndiv $t0, $t1, $t2\nrem $t3, $t1, $t2\n";
+            assert_eq!(run(div, delay_slots), Ok(3), "delay slots {}", delay_slots);
+            let div = "li $t1, 7\ndiv $t0, $t1, $zero\n";
+            let fault = run(div, delay_slots);
+            assert!(
+                matches!(fault, Err(Fault::Breakpoint { .. })),
+                "delay slots {}",
+                delay_slots
+            );
+            // an immediate divisor isn't checked
+            assert_eq!(run("li $t1, 7\ndiv $t0, $t1, 2\n", delay_slots), Ok(3));
+        }
+    }
+
+    #[test]
+    fn pseudo_instruction_addresses() {
+        // each line covers all of the instructions it expands to, so the TUI can show the line
+        // for any of them
+        let src = "li $t0, 0x12345678\ndiv $t1, $t0, $t2\nnop\n";
+        for (delay_slots, div) in [(false, 4), (true, 5)] {
+            let asm = assemble(src, &MemoryMap::MARS, delay_slots).unwrap();
+            let addrs = asm
+                .lines
+                .iter()
+                .map(|l| l.addrs.clone())
+                .collect::<Vec<_>>();
+            let div = 0x0040_0008 + div * 4;
+            let expected = [0x0040_0000..0x0040_0008, 0x0040_0008..div, div..div + 4];
+            assert_eq!(addrs, expected);
+        }
+    }
+
+    #[test]
+    fn source_lines() {
+        let src = "main: li $t0, 0x12345678\naddu $t0, $t0, $t0\n";
+        let asm = asm(src);
+        assert_eq!(
+            asm.lines,
+            [
+                SourceLine {
+                    line: 1,
+                    text: "li $t0, 0x12345678".into(),
+                    addrs: 0x0040_0000..0x0040_0008,
+                },
+                SourceLine {
+                    line: 2,
+                    text: "addu $t0, $t0, $t0".into(),
+                    addrs: 0x0040_0008..0x0040_000c,
+                },
+            ]
+        );
+        // the loaded program finds the line from any of the addresses it assembled into
+        let greg = Greg::from_asm(src, &MemoryMap::MARS, false).unwrap();
+        let debug = greg.debug.unwrap();
+        assert_eq!(debug.source_at(0x0040_0004).map(|l| l.line), Some(1));
+        assert_eq!(debug.source_at(0x0040_0008).map(|l| l.line), Some(2));
+        assert_eq!(debug.source_at(0x0040_000c), None);
+    }
+}
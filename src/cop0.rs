@@ -0,0 +1,452 @@
+//! Coprocessor 0, the exception state and the MIPS exception model
+
+use std::fmt::Display;
+
+use crate::{
+    inst::{Cop0Op, Func, Inst, InstKind, Opcode, ERET},
+    mem::Perms,
+    Greg, InstructionResult,
+};
+
+/// Where exceptions are handled, MARS uses the MIPS32 general exception vector
+pub const EXCEPTION_HANDLER: usize = 0x8000_0180;
+
+// register numbers
+pub const BAD_VADDR: usize = 8;
+pub const STATUS: usize = 12;
+pub const CAUSE: usize = 13;
+pub const EPC: usize = 14;
+
+/// Status at reset, this matches MARS (user mode, interrupts enabled)
+pub const STATUS_RESET: u32 = 0x0000_ff11;
+/// Status bit that enables interrupts
+pub const STATUS_IE: u32 = 1 << 0;
+/// Status bit set while an exception is being handled
+pub const STATUS_EXL: u32 = 1 << 1;
+/// Cause bit set when the exception happened in a branch delay slot
+pub const CAUSE_BD: u32 = 1 << 31;
+/// Cause bits for pending interrupts, Status has the masks for them in the same place
+pub const CAUSE_IP: u32 = 0xff << 8;
+const CAUSE_EXC_CODE: u32 = 0b1_1111 << 2;
+
+repr_impl! {
+    [#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]]
+    pub enum ExcCode(u8) {
+        Int = 0x00,
+        AdEL = 0x04,
+        AdES = 0x05,
+        Sys = 0x08,
+        Bp = 0x09,
+        RI = 0x0a,
+        CpU = 0x0b,
+        Ov = 0x0c,
+        Tr = 0x0d,
+    }
+}
+
+impl ExcCode {
+    pub fn description(self) -> &'static str {
+        match self {
+            ExcCode::Int => "interrupt",
+            ExcCode::AdEL => "address error on load or instruction fetch",
+            ExcCode::AdES => "address error on store",
+            ExcCode::Sys => "syscall",
+            ExcCode::Bp => "breakpoint",
+            ExcCode::RI => "reserved instruction",
+            ExcCode::CpU => "coprocessor unusable",
+            ExcCode::Ov => "arithmetic overflow",
+            ExcCode::Tr => "trap",
+        }
+    }
+}
+
+/// An exception raised by an instruction
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub struct Exception {
+    pub code: ExcCode,
+    /// The address that caused an address error
+    pub bad_vaddr: Option<u32>,
+    /// Why a `syscall` failed
+    pub syscall: Option<SyscallError>,
+    /// The instruction that raised it, filled in by [`Greg::step`]
+    pub pc: usize,
+}
+
+impl Exception {
+    pub fn new(code: ExcCode) -> Self {
+        Self {
+            code,
+            bad_vaddr: None,
+            syscall: None,
+            pc: 0,
+        }
+    }
+
+    pub fn syscall(error: SyscallError) -> Self {
+        Self {
+            syscall: Some(error),
+            ..Self::new(ExcCode::Sys)
+        }
+    }
+
+    pub fn address(code: ExcCode, addr: usize) -> Self {
+        Self {
+            bad_vaddr: Some(addr as u32),
+            ..Self::new(code)
+        }
+    }
+}
+
+impl Display for Exception {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code.description())?;
+        if let Some(error) = self.syscall {
+            write!(f, ": {}", error.description())?;
+        }
+        if let Some(addr) = self.bad_vaddr {
+            write!(f, " (address 0x{:08x})", addr)?;
+        }
+        write!(f, " at 0x{:08x}", self.pc)
+    }
+}
+
+/// Why a `syscall` could not be serviced
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub enum SyscallError {
+    /// There is no service with the number in `$v0`
+    Unknown,
+    /// The service exists in MARS but not here
+    Unimplemented,
+    /// What was read could not be parsed, e.g. `read_int` of something that is not a number
+    InvalidInput,
+    /// Standard input was closed while reading from it
+    EndOfInput,
+    /// An argument is out of the range that the service accepts
+    InvalidArgument,
+    /// `sbrk` would grow the heap past its limit
+    OutOfMemory,
+}
+
+impl SyscallError {
+    pub fn description(self) -> &'static str {
+        match self {
+            SyscallError::Unknown => "unknown service number",
+            SyscallError::Unimplemented => "service is not implemented",
+            SyscallError::InvalidInput => "invalid input",
+            SyscallError::EndOfInput => "end of input",
+            SyscallError::InvalidArgument => "invalid argument",
+            SyscallError::OutOfMemory => "out of heap memory",
+        }
+    }
+}
+
+/// The kind of memory access that faulted
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub enum Access {
+    Load,
+    Store,
+    Fetch,
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Access::Load => "load from",
+            Access::Store => "store to",
+            Access::Fetch => "instruction fetch from",
+        })
+    }
+}
+
+/// Why the program stopped, an exception that there is no handler for.
+///
+/// Each carries the address of the faulting instruction and, except when it could not be
+/// fetched, the instruction word.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub enum Fault {
+    /// The instruction at `addr` is not in mapped memory, or is not aligned
+    Fetch { addr: usize },
+    /// `word` is not an instruction, or is not one that is implemented
+    ReservedInstruction { addr: usize, word: u32 },
+    /// A device interrupted before `word`, on the lines set in `lines`
+    Interrupt { addr: usize, word: u32, lines: u8 },
+    /// `word` uses a coprocessor that isn't there
+    CoprocessorUnusable { addr: usize, word: u32 },
+    /// A load or store to `vaddr`, which is not mapped
+    Memory { addr: usize, word: u32, vaddr: u32 },
+    /// A load or store to `vaddr`, which is not aligned to the size of the access
+    Alignment { addr: usize, word: u32, vaddr: u32 },
+    /// An access to `vaddr` in the segment `region`, which its permissions don't allow
+    Protection {
+        addr: usize,
+        word: u32,
+        vaddr: u32,
+        access: Access,
+        region: &'static str,
+        perms: Perms,
+    },
+    /// Signed arithmetic overflowed
+    Overflow { addr: usize, word: u32 },
+    /// A trap instruction's condition held
+    Trap { addr: usize, word: u32 },
+    /// A `div` or `divu` by zero, with `--div-by-zero trap`
+    DivideByZero { addr: usize, word: u32 },
+    /// A `break`
+    Breakpoint { addr: usize, word: u32 },
+    /// A `syscall` failed
+    Syscall {
+        addr: usize,
+        word: u32,
+        error: SyscallError,
+    },
+}
+
+impl Fault {
+    /// The address of the faulting instruction
+    pub fn addr(&self) -> usize {
+        match *self {
+            Fault::Fetch { addr }
+            | Fault::ReservedInstruction { addr, .. }
+            | Fault::Interrupt { addr, .. }
+            | Fault::CoprocessorUnusable { addr, .. }
+            | Fault::Memory { addr, .. }
+            | Fault::Alignment { addr, .. }
+            | Fault::Protection { addr, .. }
+            | Fault::Overflow { addr, .. }
+            | Fault::Trap { addr, .. }
+            | Fault::DivideByZero { addr, .. }
+            | Fault::Breakpoint { addr, .. }
+            | Fault::Syscall { addr, .. } => addr,
+        }
+    }
+
+    /// The faulting instruction, `None` if it could not be fetched
+    pub fn word(&self) -> Option<u32> {
+        match *self {
+            Fault::Fetch { .. } => None,
+            Fault::ReservedInstruction { word, .. }
+            | Fault::Interrupt { word, .. }
+            | Fault::CoprocessorUnusable { word, .. }
+            | Fault::Memory { word, .. }
+            | Fault::Alignment { word, .. }
+            | Fault::Protection { word, .. }
+            | Fault::Overflow { word, .. }
+            | Fault::Trap { word, .. }
+            | Fault::DivideByZero { word, .. }
+            | Fault::Breakpoint { word, .. }
+            | Fault::Syscall { word, .. } => Some(word),
+        }
+    }
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::Fetch { .. } => f.write_str("instruction fetch from an unmapped or unaligned address")?,
+            Fault::ReservedInstruction { .. } => f.write_str("reserved instruction")?,
+            Fault::Interrupt { lines, .. } => {
+                let lines = (0..8)
+                    .filter(|line| lines & 1 << line != 0)
+                    .map(|line| line.to_string())
+                    .collect::<Vec<_>>();
+                match lines.len() {
+                    0 => f.write_str("interrupt")?,
+                    1 => write!(f, "interrupt on line {}", lines[0])?,
+                    _ => write!(f, "interrupt on lines {}", lines.join(", "))?,
+                }
+            }
+            Fault::CoprocessorUnusable { .. } => f.write_str("coprocessor unusable")?,
+            Fault::Memory { vaddr, .. } => write!(f, "access to unmapped address 0x{:08x}", vaddr)?,
+            Fault::Alignment { vaddr, .. } => write!(f, "unaligned access to 0x{:08x}", vaddr)?,
+            Fault::Protection {
+                vaddr,
+                access,
+                region,
+                perms,
+                ..
+            } => write!(f, "{} 0x{:08x} in {} ({})", access, vaddr, region, perms)?,
+            Fault::Overflow { .. } => f.write_str("arithmetic overflow")?,
+            Fault::Trap { .. } => f.write_str("trap")?,
+            Fault::DivideByZero { .. } => f.write_str("division by zero")?,
+            Fault::Breakpoint { .. } => f.write_str("breakpoint")?,
+            Fault::Syscall { error, .. } => write!(f, "syscall: {}", error.description())?,
+        }
+        match self.word() {
+            Some(word) => write!(f, " (0x{:08x}) at 0x{:08x}", word, self.addr()),
+            None => write!(f, " at 0x{:08x}", self.addr()),
+        }
+    }
+}
+
+impl std::error::Error for Fault {}
+
+/// Whether `word` is a `div` or `divu`, which trap on a zero divisor with `--div-by-zero trap`
+fn is_div(word: u32) -> bool {
+    let opcode = Opcode(word);
+    InstKind::new(opcode.op()) == Some(InstKind::Special)
+        && matches!(Func::new(opcode.func()), Some(Func::Div | Func::DivU))
+}
+
+/// Raise an address error unless `addr` is a multiple of `align`
+pub fn aligned(addr: usize, align: usize, code: ExcCode) -> Result<usize, Exception> {
+    if addr.is_multiple_of(align) {
+        Ok(addr)
+    } else {
+        Err(Exception::address(code, addr))
+    }
+}
+
+/// Raise an integer overflow exception if a signed operation (`add`, `addi`, `sub`) overflowed
+pub fn overflow(result: Option<i32>) -> Result<u32, Exception> {
+    result
+        .map(|value| value as u32)
+        .ok_or(Exception::new(ExcCode::Ov))
+}
+
+impl Greg {
+    /// The address that exceptions jump to
+    pub fn handler(&self) -> usize {
+        self.handler.unwrap_or(EXCEPTION_HANDLER)
+    }
+
+    /// Whether code is mapped at the exception handler, otherwise exceptions stop the program
+    pub fn has_handler(&self) -> bool {
+        self.memory
+            .perms(self.handler())
+            .is_some_and(|perms| perms.exec)
+    }
+
+    /// Whether an interrupt should be taken before the next instruction.  Devices asking for one
+    /// are shown in the pending bits of Cause, they are taken while Status enables them and no
+    /// exception is being handled.
+    pub fn interrupt_pending(&mut self) -> bool {
+        let pending = self.memory.interrupts();
+        self.cop0[CAUSE] = (self.cop0[CAUSE] & !CAUSE_IP) | pending;
+        let status = self.cop0[STATUS];
+        status & STATUS_IE != 0
+            && status & STATUS_EXL == 0
+            && status & pending != 0
+            && self.has_handler()
+    }
+
+    /// Record `exc` in coprocessor 0 and transfer control to the exception handler, `branch` is
+    /// the pending branch if `exc` happened in a delay slot.
+    ///
+    /// Without a handler the program stops with a [`Fault`] at the instruction, so that it can
+    /// be inspected.  A `break` is left as if it had finished instead, so that a debugger may
+    /// resume after it.
+    pub fn raise(
+        &mut self,
+        exc: Exception,
+        branch: Option<usize>,
+    ) -> Result<InstructionResult, Fault> {
+        if !self.has_handler() {
+            return Err(self.fault(exc, branch));
+        }
+
+        // EPC and BD are left alone for exceptions inside of the handler
+        if self.cop0[STATUS] & STATUS_EXL == 0 {
+            if branch.is_some() {
+                // restart from the branch so that it is taken again
+                self.cop0[EPC] = (exc.pc as u32).wrapping_sub(4);
+                self.cop0[CAUSE] |= CAUSE_BD;
+            } else {
+                self.cop0[EPC] = exc.pc as u32;
+                self.cop0[CAUSE] &= !CAUSE_BD;
+            }
+        }
+        self.cop0[CAUSE] = (self.cop0[CAUSE] & !CAUSE_EXC_CODE) | (exc.code as u32) << 2;
+        if let Some(addr) = exc.bad_vaddr {
+            self.cop0[BAD_VADDR] = addr;
+        }
+        self.cop0[STATUS] |= STATUS_EXL;
+        self.branch = None;
+        self.ip = self.handler();
+        Ok(InstructionResult::None)
+    }
+
+    fn fault(&mut self, exc: Exception, branch: Option<usize>) -> Fault {
+        let addr = exc.pc;
+        if exc.code == ExcCode::Bp {
+            if let Some(target) = branch {
+                self.ip = target;
+            }
+        } else {
+            self.ip = addr;
+        }
+        let Some(word) = self.word_at(addr) else {
+            // code that isn't executable can still be shown
+            return match self.memory.segment(addr) {
+                Some(segment) if addr.is_multiple_of(4) => Fault::Protection {
+                    addr,
+                    word: self.memory.get_u32(addr).unwrap_or_default(),
+                    vaddr: addr as u32,
+                    access: Access::Fetch,
+                    region: segment.name,
+                    perms: segment.perms,
+                },
+                _ => Fault::Fetch { addr },
+            };
+        };
+        let vaddr = exc.bad_vaddr.unwrap_or_default();
+        match exc.code {
+            ExcCode::AdEL | ExcCode::AdES => {
+                let (access, allowed): (_, fn(Perms) -> bool) = if exc.code == ExcCode::AdES {
+                    (Access::Store, |perms| perms.write)
+                } else {
+                    (Access::Load, |perms| perms.read)
+                };
+                // range checks give the first byte that can't be accessed, so if this one can
+                // it was the alignment that was wrong
+                match self.memory.segment(vaddr as usize) {
+                    None => Fault::Memory { addr, word, vaddr },
+                    Some(segment) if !self.no_protect && !allowed(segment.perms) => {
+                        Fault::Protection {
+                            addr,
+                            word,
+                            vaddr,
+                            access,
+                            region: segment.name,
+                            perms: segment.perms,
+                        }
+                    }
+                    Some(_) => Fault::Alignment { addr, word, vaddr },
+                }
+            }
+            ExcCode::Ov => Fault::Overflow { addr, word },
+            // a handler sees a trap either way, only the error tells them apart
+            ExcCode::Tr if is_div(word) => Fault::DivideByZero { addr, word },
+            ExcCode::Tr => Fault::Trap { addr, word },
+            ExcCode::Bp => Fault::Breakpoint { addr, word },
+            ExcCode::Sys => Fault::Syscall {
+                addr,
+                word,
+                error: exc.syscall.unwrap_or(SyscallError::Unknown),
+            },
+            ExcCode::Int => Fault::Interrupt {
+                addr,
+                word,
+                lines: ((self.memory.interrupts() & CAUSE_IP) >> 8) as u8,
+            },
+            ExcCode::RI => Fault::ReservedInstruction { addr, word },
+            ExcCode::CpU => Fault::CoprocessorUnusable { addr, word },
+        }
+    }
+
+    pub fn cop0(&mut self, inst: Inst) -> Result<InstructionResult, Exception> {
+        let rt = inst.opcode.rt();
+        let rd = inst.opcode.rd() as usize;
+        match inst.cop0_op() {
+            Some(Cop0Op::Mf) => self[rt] = self.cop0[rd],
+            Some(Cop0Op::Mt) => self.cop0[rd] = self[rt],
+            Some(Cop0Op::Co) if inst.opcode.func() == ERET => {
+                // eret has no delay slot
+                self.cop0[STATUS] &= !STATUS_EXL;
+                self.memory.unlink();
+                self.ip = self.cop0[EPC] as usize;
+            }
+            _ => return Err(Exception::new(ExcCode::RI)),
+        }
+        Ok(InstructionResult::None)
+    }
+}
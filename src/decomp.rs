@@ -1,6 +1,6 @@
 use crate::{
-    inst::{Func, Inst, InstKind},
-    reg::Reg,
+    inst::{Cop0Op, Fmt, Func, Inst, InstKind, RegImm, Special2, Special3, ERET},
+    reg::{FReg, Reg},
     DebugInfo,
 };
 
@@ -8,12 +8,25 @@ use crate::{
 pub enum Addr {
     Label(String),
     Relative(i32),
+    Absolute(usize),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum DecompKind {
     Syscall,
+    Eret,
     Nop,
+    /// Word - .word w, a word that is not a valid instruction
+    Word(u32),
+    /// Sync - sync stype
+    Sync {
+        stype: u32,
+    },
+    /// Break - break code, subcode
+    Break {
+        code: u32,
+        subcode: u32,
+    },
     Label(String),
     /// ArithLog - f $d, $s, $t
     ArithLog {
@@ -42,6 +55,33 @@ pub enum DecompKind {
         t: Reg,
         s: Reg,
     },
+    /// Unary - f $d, $s
+    Unary {
+        f: Inst,
+        d: Reg,
+        s: Reg,
+    },
+    /// BitField - f $t, $s, pos, size
+    BitField {
+        f: Inst,
+        t: Reg,
+        s: Reg,
+        pos: u32,
+        size: u32,
+    },
+    /// Trap - f $s, $t, code
+    Trap {
+        f: Inst,
+        s: Reg,
+        t: Reg,
+        code: u32,
+    },
+    /// TrapI - o $s, i
+    TrapI {
+        o: Inst,
+        s: Reg,
+        i: i32,
+    },
     /// JumpR - f $s
     JumpR {
         f: Inst,
@@ -90,11 +130,63 @@ pub enum DecompKind {
         t: Reg,
         i: i32,
     },
+    /// Cache - o op, i ($s)
+    Cache {
+        o: Inst,
+        op: u32,
+        s: Reg,
+        i: i32,
+    },
     /// Jump - o label
     Jump {
         o: Inst,
         pos: Addr,
     },
+    /// Cop0Move - f $t, $d, where $d is a coprocessor 0 register
+    Cop0Move {
+        f: Inst,
+        t: Reg,
+        d: u8,
+    },
+    /// FpuArith - f.fmt fd, fs, ft
+    FpuArith {
+        f: Inst,
+        d: FReg,
+        s: FReg,
+        t: FReg,
+    },
+    /// FpuUnary - f.fmt fd, fs
+    FpuUnary {
+        f: Inst,
+        d: FReg,
+        s: FReg,
+    },
+    /// FpuCompare - c.cond.fmt cc, fs, ft
+    FpuCompare {
+        f: Inst,
+        cc: u8,
+        s: FReg,
+        t: FReg,
+    },
+    /// FpuMove - f $t, fs
+    FpuMove {
+        f: Inst,
+        t: Reg,
+        s: FReg,
+    },
+    /// FpuBranch - o cc, label
+    FpuBranch {
+        o: Inst,
+        cc: u8,
+        pos: Addr,
+    },
+    /// FpuLoadStore - o ft, i ($s)
+    FpuLoadStore {
+        o: Inst,
+        s: Reg,
+        t: FReg,
+        i: i32,
+    },
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -106,12 +198,20 @@ impl Decomp {
     pub fn active_label(&self) -> Option<&str> {
         match &self.kind {
             DecompKind::Syscall => None,
+            DecompKind::Eret => None,
             DecompKind::Nop => None,
+            DecompKind::Word(_) => None,
+            DecompKind::Break { .. } => None,
+            DecompKind::Sync { .. } => None,
             DecompKind::Label(_) => None,
             DecompKind::ArithLog { .. } => None,
             DecompKind::DivMult { .. } => None,
             DecompKind::Shift { .. } => None,
             DecompKind::ShiftV { .. } => None,
+            DecompKind::Unary { .. } => None,
+            DecompKind::BitField { .. } => None,
+            DecompKind::Trap { .. } => None,
+            DecompKind::TrapI { .. } => None,
             DecompKind::JumpR { .. } => None,
             DecompKind::MoveFrom { .. } => None,
             DecompKind::MoveTo { .. } => None,
@@ -126,31 +226,51 @@ impl Decomp {
                 pos: Addr::Label(pos),
                 ..
             } => Some(pos),
-            DecompKind::BranchZ { .. } => todo!(),
+            DecompKind::BranchZ { .. } => None,
             DecompKind::LoadStore { .. } => None,
+            DecompKind::Cache { .. } => None,
             DecompKind::Jump {
                 pos: Addr::Label(pos),
                 ..
             } => Some(pos),
             DecompKind::Jump { .. } => None,
+            DecompKind::Cop0Move { .. } => None,
+            DecompKind::FpuArith { .. } => None,
+            DecompKind::FpuUnary { .. } => None,
+            DecompKind::FpuCompare { .. } => None,
+            DecompKind::FpuMove { .. } => None,
+            DecompKind::FpuBranch {
+                pos: Addr::Label(pos),
+                ..
+            } => Some(pos),
+            DecompKind::FpuBranch { .. } => None,
+            DecompKind::FpuLoadStore { .. } => None,
         }
     }
 }
 
 impl DecompKind {
     fn resolve_label(ip: usize, relative: i32, debug: Option<&DebugInfo>) -> Addr {
-        let Some(debug) = debug else {
-            return Addr::Relative(relative);
-        };
         let Some(ip) = ip.checked_add_signed((relative as isize + 1) * 4) else {
             return Addr::Relative(relative);
         };
 
-        let Some((label, _)) = debug.labels.iter().find(|(_, v)| **v == ip) else {
-            return Addr::Relative(relative);
-        };
+        match Self::find_label(ip, debug) {
+            Some(label) => Addr::Label(label),
+            None => Addr::Relative(relative),
+        }
+    }
 
-        Addr::Label(label.to_string())
+    fn resolve_absolute(addr: usize, debug: Option<&DebugInfo>) -> Addr {
+        match Self::find_label(addr, debug) {
+            Some(label) => Addr::Label(label),
+            None => Addr::Absolute(addr),
+        }
+    }
+
+    fn find_label(addr: usize, debug: Option<&DebugInfo>) -> Option<String> {
+        let (label, _) = debug?.labels.iter().find(|(_, v)| **v == addr)?;
+        Some(label.to_string())
     }
     pub fn from(inst: Inst, ip: usize, debug: Option<&DebugInfo>) -> Self {
         macro_rules! make {
@@ -172,6 +292,15 @@ impl DecompKind {
                     s: Reg::from(imm.rs),
                 }
             }};
+            (FpuLoadStore) => {{
+                let imm = inst.imm();
+                DecompKind::FpuLoadStore {
+                    o: inst,
+                    t: FReg::from(imm.rt),
+                    i: imm.imm as i32,
+                    s: Reg::from(imm.rs),
+                }
+            }};
             (Branch) => {{
                 let imm = inst.imm();
                 if imm.rs == 0 && imm.rt == 0 {
@@ -192,6 +321,12 @@ impl DecompKind {
                     }
                 }
             }};
+            (BranchAlways) => {{
+                DecompKind::Jump {
+                    o: inst,
+                    pos: Self::resolve_label(ip, inst.imm().imm.into(), debug),
+                }
+            }};
             (BranchZ) => {{
                 let imm = inst.imm();
                 DecompKind::BranchZ {
@@ -201,10 +336,9 @@ impl DecompKind {
                 }
             }};
             (Jump) => {{
-                let jmp = inst.jmp();
                 DecompKind::Jump {
                     o: inst,
-                    pos: Self::resolve_label(ip, jmp, debug),
+                    pos: Self::resolve_absolute(inst.jump_target(ip + 4), debug),
                 }
             }};
             (ArithLog) => {{
@@ -265,37 +399,70 @@ impl DecompKind {
             }};
         }
         match inst.kind {
-            InstKind::Special => match inst.func().unwrap() {
-                Func::Sll if inst.opcode.0 == 0 => DecompKind::Nop,
-                Func::Sll => make!(Shift),
-                Func::Srl => make!(Shift),
-                Func::Sra => make!(Shift),
-                Func::Sllv => make!(ShiftV),
-                Func::Srlv => make!(ShiftV),
-                Func::Srav => make!(ShiftV),
-                Func::Jr => make!(JumpR),
-                Func::Jalr => make!(JumpR),
-                Func::Syscall => DecompKind::Syscall,
-                Func::Mfhi => make!(MoveFrom),
-                Func::Mthi => make!(MoveTo),
-                Func::Mflo => make!(MoveFrom),
-                Func::Mtlo => make!(MoveTo),
-                Func::Mult => make!(DivMult),
-                Func::MultU => make!(DivMult),
-                Func::Div => make!(DivMult),
-                Func::DivU => make!(DivMult),
-                Func::Add => make!(ArithLog),
-                Func::Addu => make!(ArithLog),
-                Func::Sub => make!(ArithLog),
-                Func::Subu => make!(ArithLog),
-                Func::And => make!(ArithLog),
-                Func::Or => make!(ArithLog),
-                Func::Xor => make!(ArithLog),
-                Func::Nor => make!(ArithLog),
-                Func::Slt => make!(ArithLog),
-                Func::Sltu => make!(ArithLog),
+            InstKind::Special => match inst.func() {
+                None => DecompKind::Word(inst.opcode.0),
+                Some(func) => match func {
+                    Func::Sll if inst.opcode.0 == 0 => DecompKind::Nop,
+                    Func::Sll => make!(Shift),
+                    Func::Srl => make!(Shift),
+                    Func::Sra => make!(Shift),
+                    Func::Sllv => make!(ShiftV),
+                    Func::Srlv => make!(ShiftV),
+                    Func::Srav => make!(ShiftV),
+                    Func::Jr => make!(JumpR),
+                    Func::Jalr => make!(JumpR),
+                    Func::Movz => make!(ArithLog),
+                    Func::Movn => make!(ArithLog),
+                    Func::Syscall => DecompKind::Syscall,
+                    Func::Sync => DecompKind::Sync {
+                        stype: inst.reg().shift as u32,
+                    },
+                    Func::Break => DecompKind::Break {
+                        code: inst.opcode.0 >> 16 & 0x3ff,
+                        subcode: inst.code(),
+                    },
+                    Func::Mfhi => make!(MoveFrom),
+                    Func::Mthi => make!(MoveTo),
+                    Func::Mflo => make!(MoveFrom),
+                    Func::Mtlo => make!(MoveTo),
+                    Func::Mult => make!(DivMult),
+                    Func::MultU => make!(DivMult),
+                    Func::Div => make!(DivMult),
+                    Func::DivU => make!(DivMult),
+                    Func::Add => make!(ArithLog),
+                    Func::Addu => make!(ArithLog),
+                    Func::Sub => make!(ArithLog),
+                    Func::Subu => make!(ArithLog),
+                    Func::And => make!(ArithLog),
+                    Func::Or => make!(ArithLog),
+                    Func::Xor => make!(ArithLog),
+                    Func::Nor => make!(ArithLog),
+                    Func::Slt => make!(ArithLog),
+                    Func::Sltu => make!(ArithLog),
+                    Func::Tge | Func::Tgeu | Func::Tlt | Func::Tltu | Func::Teq | Func::Tne => {
+                        let reg = inst.reg();
+                        DecompKind::Trap {
+                            f: inst,
+                            s: Reg::from(reg.rs),
+                            t: Reg::from(reg.rt),
+                            code: inst.code(),
+                        }
+                    }
+                },
+            },
+            InstKind::RegImm => match inst.regimm() {
+                Some(RegImm::Bgez | RegImm::Bgezal) if inst.opcode.rs() == 0 => make!(BranchAlways),
+                Some(op) if op.is_trap() => {
+                    let imm = inst.imm();
+                    DecompKind::TrapI {
+                        o: inst,
+                        s: Reg::from(imm.rs),
+                        i: imm.imm as i32,
+                    }
+                }
+                Some(_) => make!(BranchZ),
+                None => DecompKind::Word(inst.opcode.0),
             },
-            InstKind::Bal => make!(Branch),
             InstKind::J => make!(Jump),
             InstKind::Jal => make!(Jump),
             InstKind::Beq => make!(Branch),
@@ -310,18 +477,148 @@ impl DecompKind {
             InstKind::OrI => make!(ArithLogI),
             InstKind::XorI => make!(ArithLogI),
             InstKind::LUI => make!(ArithLogI),
-            InstKind::Mfc0 => todo!(),
+            InstKind::Cop0 => match inst.cop0_op() {
+                Some(Cop0Op::Mf | Cop0Op::Mt) => DecompKind::Cop0Move {
+                    f: inst,
+                    t: Reg::from(inst.opcode.rt()),
+                    d: inst.opcode.rd(),
+                },
+                Some(Cop0Op::Co) if inst.opcode.func() == ERET => DecompKind::Eret,
+                _ => DecompKind::Word(inst.opcode.0),
+            },
             InstKind::LB => make!(LoadStore),
+            InstKind::LH => make!(LoadStore),
+            InstKind::LWL => make!(LoadStore),
             InstKind::LW => make!(LoadStore),
             InstKind::LBU => make!(LoadStore),
             InstKind::LHU => make!(LoadStore),
+            InstKind::LWR => make!(LoadStore),
             InstKind::SB => make!(LoadStore),
             InstKind::SH => make!(LoadStore),
+            InstKind::SWL => make!(LoadStore),
             InstKind::SW => make!(LoadStore),
-            InstKind::Cache => todo!(),
-            InstKind::LL => todo!(),
-            InstKind::Lwci => todo!(),
-            InstKind::Sc => todo!(),
+            InstKind::SWR => make!(LoadStore),
+            InstKind::Special2 => match inst.special2() {
+                Some(Special2::Mul) => make!(ArithLog),
+                Some(Special2::Clz | Special2::Clo) => {
+                    let reg = inst.reg();
+                    DecompKind::Unary {
+                        f: inst,
+                        d: Reg::from(reg.rd),
+                        s: Reg::from(reg.rs),
+                    }
+                }
+                Some(_) => make!(DivMult),
+                None => DecompKind::Word(inst.opcode.0),
+            },
+            InstKind::Special3 => {
+                let reg = inst.reg();
+                match inst.special3() {
+                    Some(Special3::Ext | Special3::Ins) => {
+                        let (pos, size) = inst.bit_field();
+                        DecompKind::BitField {
+                            f: inst,
+                            t: Reg::from(reg.rt),
+                            s: Reg::from(reg.rs),
+                            pos: pos as u32,
+                            size: size as u32,
+                        }
+                    }
+                    // the byte shuffles read `rt`
+                    Some(Special3::Bshfl) if inst.bshfl().is_some() => DecompKind::Unary {
+                        f: inst,
+                        d: Reg::from(reg.rd),
+                        s: Reg::from(reg.rt),
+                    },
+                    _ => DecompKind::Word(inst.opcode.0),
+                }
+            }
+            InstKind::Cop1 => Self::from_cop1(inst, ip, debug),
+            InstKind::Lwc1 => make!(FpuLoadStore),
+            InstKind::Ldc1 => make!(FpuLoadStore),
+            InstKind::Swc1 => make!(FpuLoadStore),
+            InstKind::Sdc1 => make!(FpuLoadStore),
+            InstKind::Cache => {
+                let imm = inst.imm();
+                DecompKind::Cache {
+                    o: inst,
+                    op: imm.rt as u32,
+                    s: Reg::from(imm.rs),
+                    i: imm.imm as i32,
+                }
+            }
+            InstKind::LL => make!(LoadStore),
+            InstKind::Sc => make!(LoadStore),
+        }
+    }
+
+    fn from_cop1(inst: Inst, ip: usize, debug: Option<&DebugInfo>) -> Self {
+        let reg = inst.reg();
+        // fs is in `rd` and fd is in `shift`
+        let (d, s, t) = (FReg(reg.shift), FReg(reg.rd), FReg(reg.rt));
+        match inst.fmt() {
+            Some(Fmt::Mf | Fmt::Mt) => DecompKind::FpuMove {
+                f: inst,
+                t: Reg::from(reg.rt),
+                s,
+            },
+            Some(Fmt::Bc) => DecompKind::FpuBranch {
+                o: inst,
+                cc: reg.rt >> 2,
+                pos: Self::resolve_label(ip, inst.imm().imm.into(), debug),
+            },
+            Some(_) => match inst.fpu_func() {
+                Some(func) if func.is_compare() => DecompKind::FpuCompare {
+                    f: inst,
+                    cc: reg.shift >> 2,
+                    s,
+                    t,
+                },
+                Some(func) if func.is_unary() => DecompKind::FpuUnary { f: inst, d, s },
+                Some(_) => DecompKind::FpuArith { f: inst, d, s, t },
+                None => DecompKind::Word(inst.opcode.0),
+            },
+            None => DecompKind::Word(inst.opcode.0),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use super::*;
+
+    fn debug(labels: &[(&str, usize)]) -> DebugInfo {
+        DebugInfo {
+            labels: HashMap::from_iter(labels.iter().map(|&(l, a)| (l.to_string(), a))),
+            source: BTreeMap::new(),
+        }
+    }
+
+    fn jump_pos(inst: Inst, ip: usize, debug: Option<&DebugInfo>) -> Addr {
+        match DecompKind::from(inst, ip, debug) {
+            DecompKind::Jump { pos, .. } => pos,
+            kind => panic!("{:?} is not a jump", kind),
+        }
+    }
+
+    #[test]
+    fn far_jumps_use_the_region_of_the_delay_slot() {
+        let j = Inst::j_type(InstKind::J, 0x40);
+        // the delay slot of a jump in the last word of a region is in the next region
+        assert_eq!(jump_pos(j, 0x0fff_fffc, None), Addr::Absolute(0x1000_0100));
+        assert_eq!(jump_pos(j, 0x0fff_fff8, None), Addr::Absolute(0x0000_0100));
+
+        let debug = debug(&[("near", 0x0000_0100), ("far", 0x1000_0100)]);
+        let jal = Inst::j_type(InstKind::Jal, 0x40);
+        assert_eq!(
+            jump_pos(jal, 0x0fff_fffc, Some(&debug)),
+            Addr::Label("far".into())
+        );
+        assert_eq!(
+            jump_pos(jal, 0x0fff_fff8, Some(&debug)),
+            Addr::Label("near".into())
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! Coprocessor 1, the floating point unit
+
+use crate::{
+    cop0::{aligned, ExcCode, Exception},
+    inst::{Fmt, FpuFunc, Imm, Inst, InstKind},
+    Greg, InstructionResult,
+};
+
+/// The FCSR rounding mode bits
+const FCSR_RM: u32 = 0b11;
+/// FCSR bit of condition flag 0, flags 1-7 are at bits 25-31
+const FCSR_FCC0: u32 = 23;
+
+impl Greg {
+    pub fn get_f32(&self, r: u8) -> f32 {
+        f32::from_bits(self.freg[r as usize])
+    }
+
+    pub fn set_f32(&mut self, r: u8, value: f32) {
+        self.freg[r as usize] = value.to_bits();
+    }
+
+    /// Doubles are held in an even/odd pair, with the low word in the even register.  An odd
+    /// register is a reserved instruction.
+    pub fn get_f64(&self, r: u8) -> Result<f64, Exception> {
+        let r = pair(r)?;
+        Ok(f64::from_bits(
+            (self.freg[r + 1] as u64) << 32 | self.freg[r] as u64,
+        ))
+    }
+
+    pub fn set_f64(&mut self, r: u8, value: f64) -> Result<(), Exception> {
+        let r = pair(r)?;
+        let bits = value.to_bits();
+        self.freg[r] = bits as u32;
+        self.freg[r + 1] = (bits >> 32) as u32;
+        Ok(())
+    }
+
+    /// Condition flag `cc`, set by `c.cond` and tested by `bc1t`/`bc1f`
+    pub fn fcc(&self, cc: u8) -> bool {
+        self.fcsr & (1 << fcc_bit(cc)) != 0
+    }
+
+    fn set_fcc(&mut self, cc: u8, value: bool) {
+        if value {
+            self.fcsr |= 1 << fcc_bit(cc);
+        } else {
+            self.fcsr &= !(1 << fcc_bit(cc));
+        }
+    }
+
+    /// Read `fs` in the format `fmt`, widened so that every format can share an implementation
+    fn get_fmt(&self, fmt: Fmt, r: u8) -> Result<f64, Exception> {
+        Ok(match fmt {
+            Fmt::S => self.get_f32(r) as f64,
+            Fmt::D => self.get_f64(r)?,
+            Fmt::W => self.freg[r as usize] as i32 as f64,
+            Fmt::Mf | Fmt::Mt | Fmt::Bc => unreachable!("{:?} is not an arithmetic format", fmt),
+        })
+    }
+
+    fn set_fmt(&mut self, fmt: Fmt, r: u8, value: f64) -> Result<(), Exception> {
+        match fmt {
+            Fmt::S => self.set_f32(r, value as f32),
+            Fmt::D => self.set_f64(r, value)?,
+            Fmt::W => self.freg[r as usize] = to_word(value) as u32,
+            Fmt::Mf | Fmt::Mt | Fmt::Bc => unreachable!("{:?} is not an arithmetic format", fmt),
+        }
+        Ok(())
+    }
+
+    pub fn cop1(&mut self, inst: Inst) -> Result<InstructionResult, Exception> {
+        let Some(fmt) = inst.fmt() else {
+            return Err(Exception::new(ExcCode::RI));
+        };
+        let rt = inst.opcode.rt();
+        let fs = inst.opcode.rd();
+        match fmt {
+            Fmt::Mf => self[rt] = self.freg[fs as usize],
+            Fmt::Mt => self.freg[fs as usize] = self[rt],
+            Fmt::Bc => {
+                let Imm { imm, .. } = inst.imm();
+                let on_true = rt & 1 != 0;
+                let likely = rt & 2 != 0;
+                if self.fcc(rt >> 2) == on_true {
+                    let imm = (imm as i32) << 2;
+                    self.jump(self.ip.wrapping_add_signed(imm as isize));
+                } else if likely && self.delay_slots {
+                    // branch likely only runs the delay slot when it is taken
+                    self.ip += 4;
+                }
+            }
+            Fmt::S | Fmt::D | Fmt::W => {
+                let Some(func) = inst.fpu_func().filter(|f| f.formats().contains(&fmt)) else {
+                    return Err(Exception::new(ExcCode::RI));
+                };
+                let ft = rt;
+                let fd = inst.opcode.shift();
+                // doubles are in even/odd pairs, so an odd register is not a valid operand
+                let result_fmt = func.result(fmt);
+                if (fmt == Fmt::D && (fs | ft) & 1 != 0)
+                    || (result_fmt == Some(Fmt::D) && fd & 1 != 0)
+                {
+                    return Err(Exception::new(ExcCode::RI));
+                }
+                let s = self.get_fmt(fmt, fs)?;
+
+                if func.is_compare() {
+                    let t = self.get_fmt(fmt, ft)?;
+                    // the condition flag is in the top 3 bits of `fd`
+                    self.set_fcc(fd >> 2, func.compare(s, t));
+                    return Ok(InstructionResult::None);
+                }
+
+                // single precision is computed as doubles, which rounds the same as doing it in
+                // f32 for these operations
+                let result = match func {
+                    FpuFunc::Add => s + self.get_fmt(fmt, ft)?,
+                    FpuFunc::Sub => s - self.get_fmt(fmt, ft)?,
+                    FpuFunc::Mul => s * self.get_fmt(fmt, ft)?,
+                    FpuFunc::Div => s / self.get_fmt(fmt, ft)?,
+                    FpuFunc::Sqrt => s.sqrt(),
+                    FpuFunc::Abs => s.abs(),
+                    FpuFunc::Neg => -s,
+                    FpuFunc::Mov => {
+                        // copy the bits so that NaN payloads are kept
+                        self.freg[fd as usize] = self.freg[fs as usize];
+                        if fmt == Fmt::D {
+                            self.freg[fd as usize + 1] = self.freg[fs as usize + 1];
+                        }
+                        return Ok(InstructionResult::None);
+                    }
+                    FpuFunc::RoundW => s.round_ties_even(),
+                    FpuFunc::TruncW => s.trunc(),
+                    FpuFunc::CeilW => s.ceil(),
+                    FpuFunc::FloorW => s.floor(),
+                    FpuFunc::CvtW => match self.fcsr & FCSR_RM {
+                        0 => s.round_ties_even(),
+                        1 => s.trunc(),
+                        2 => s.ceil(),
+                        _ => s.floor(),
+                    },
+                    FpuFunc::CvtS | FpuFunc::CvtD => s,
+                    _ => unreachable!("comparisons are handled above"),
+                };
+                let result_fmt = result_fmt.expect("only comparisons have no result");
+                self.set_fmt(result_fmt, fd, result)?;
+            }
+        }
+        Ok(InstructionResult::None)
+    }
+
+    /// `lwc1`, `ldc1`, `swc1` and `sdc1`
+    pub fn cop1_load_store(&mut self, inst: Inst) -> Result<(), Exception> {
+        let Imm { rs, rt: ft, imm } = inst.imm();
+        let addr = self.effective_addr(rs, imm);
+        let ft = ft as usize;
+        if matches!(inst.kind, InstKind::Ldc1 | InstKind::Sdc1) && !ft.is_multiple_of(2) {
+            return Err(Exception::new(ExcCode::RI));
+        }
+        match inst.kind {
+            InstKind::Lwc1 => {
+                let addr = aligned(addr, 4, ExcCode::AdEL)?;
+                self.freg[ft] = self.load_u32(addr)?;
+            }
+            InstKind::Swc1 => {
+                let addr = aligned(addr, 4, ExcCode::AdES)?;
+                self.store_u32(addr, self.freg[ft])?;
+            }
+            InstKind::Ldc1 => {
+                let addr = aligned(addr, 8, ExcCode::AdEL)?;
+                let value =
+                    f64::from_bits((self.load_u32(addr + 4)? as u64) << 32 | self.load_u32(addr)? as u64);
+                self.set_f64(ft as u8, value)?;
+            }
+            InstKind::Sdc1 => {
+                let addr = aligned(addr, 8, ExcCode::AdES)?;
+                let bits = self.get_f64(ft as u8)?.to_bits();
+                self.store_u32(addr, bits as u32)?;
+                self.store_u32(addr + 4, (bits >> 32) as u32)?;
+            }
+            kind => unreachable!("{:?} is not a cop1 load or store", kind),
+        }
+        Ok(())
+    }
+}
+
+/// The index of the even register of the pair `$f{r}`, `$f{r + 1}`
+fn pair(r: u8) -> Result<usize, Exception> {
+    if r.is_multiple_of(2) && r < 32 {
+        Ok(r as usize)
+    } else {
+        Err(Exception::new(ExcCode::RI))
+    }
+}
+
+fn fcc_bit(cc: u8) -> u32 {
+    debug_assert!(cc < 8, "there are 8 condition flags");
+    if cc == 0 {
+        FCSR_FCC0
+    } else {
+        24 + cc as u32
+    }
+}
+
+/// Convert an already rounded value to a word, values that do not fit (and NaN) become
+/// 2^31 - 1, the result that MIPS gives for an invalid conversion
+fn to_word(value: f64) -> i32 {
+    if value.is_nan() || value < i32::MIN as f64 || value > i32::MAX as f64 {
+        i32::MAX
+    } else {
+        value as i32
+    }
+}
+
+/// Format a float the way that MARS (Java) prints it, `1.0`, `0.1` and `1.0E10`
+pub fn java_float<F>(value: F) -> String
+where
+    F: Into<f64> + Copy + std::fmt::Debug + std::fmt::LowerExp,
+{
+    let shortest = format!("{:?}", value);
+    let exp = format!("{:e}", value);
+    let value: f64 = value.into();
+    if value.is_nan() {
+        return "NaN".into();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "Infinity" } else { "-Infinity" }.into();
+    }
+    let abs = value.abs();
+    if abs == 0.0 || (1e-3..1e7).contains(&abs) {
+        return shortest;
+    }
+    let (mantissa, exp) = exp.split_once('e').expect("LowerExp always has an exponent");
+    if mantissa.contains('.') {
+        format!("{}E{}", mantissa, exp)
+    } else {
+        format!("{}.0E{}", mantissa, exp)
+    }
+}
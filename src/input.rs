@@ -0,0 +1,73 @@
+//! Standard input, shared by the `read_*` syscalls and the keyboard
+//!
+//! Stdin is read a byte at a time on another thread, so that the keyboard can see whether a key
+//! is ready without blocking.  Everything that reads stdin goes through here, so bytes that the
+//! thread has read ahead are not lost to the syscalls.
+
+use std::{
+    io::{self, Read},
+    sync::{
+        mpsc::{self, Receiver},
+        Mutex,
+    },
+    thread,
+};
+
+struct Input {
+    bytes: Receiver<u8>,
+    // A byte that has been looked at but not taken
+    peeked: Option<u8>,
+}
+
+impl Input {
+    fn spawn() -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                let Ok(byte) = byte else { break };
+                if tx.send(byte).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            bytes: rx,
+            peeked: None,
+        }
+    }
+}
+
+// Only started once something reads stdin
+static INPUT: Mutex<Option<Input>> = Mutex::new(None);
+
+fn with<T>(f: impl FnOnce(&mut Input) -> T) -> T {
+    let mut input = INPUT.lock().unwrap_or_else(|e| e.into_inner());
+    f(input.get_or_insert_with(Input::spawn))
+}
+
+/// The next byte if there is one already, without taking it
+pub fn peek() -> Option<u8> {
+    with(|input| {
+        if input.peeked.is_none() {
+            input.peeked = input.bytes.try_recv().ok();
+        }
+        input.peeked
+    })
+}
+
+/// Take the next byte, waiting for it, `None` at the end of input
+pub fn next() -> Option<u8> {
+    with(|input| input.peeked.take().or_else(|| input.bytes.recv().ok()))
+}
+
+/// Take the bytes up to and including the next newline, `None` at the end of input
+pub fn line() -> Option<Vec<u8>> {
+    let mut line = Vec::new();
+    while let Some(byte) = next() {
+        line.push(byte);
+        if byte == b'\n' {
+            break;
+        }
+    }
+    (!line.is_empty()).then_some(line)
+}
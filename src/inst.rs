@@ -44,6 +44,34 @@ impl Opcode {
     pub fn address(self) -> i32 {
         (self.0 & !(0b11_1111u32 << 26u32)) as i32
     }
+
+    /// Pack the fields of a register layout instruction, the inverse of the getters above
+    pub fn encode(op: u8, rs: u8, rt: u8, rd: u8, shift: u8, func: u8) -> Self {
+        debug_assert!(op < 64 && func < 64, "op and func are 6 bits");
+        debug_assert!(rs < 32 && rt < 32 && rd < 32 && shift < 32, "fields are 5 bits");
+        Self(
+            (op as u32) << 26
+                | (rs as u32) << 21
+                | (rt as u32) << 16
+                | (rd as u32) << 11
+                | (shift as u32) << 6
+                | func as u32,
+        )
+    }
+
+    /// Pack the fields of an immediate layout instruction
+    pub fn encode_imm(op: u8, rs: u8, rt: u8, imm: i16) -> Self {
+        debug_assert!(op < 64, "op is 6 bits");
+        debug_assert!(rs < 32 && rt < 32, "fields are 5 bits");
+        Self((op as u32) << 26 | (rs as u32) << 21 | (rt as u32) << 16 | imm as u16 as u32)
+    }
+
+    /// Pack the fields of a jump layout instruction
+    pub fn encode_jump(op: u8, address: u32) -> Self {
+        debug_assert!(op < 64, "op is 6 bits");
+        debug_assert!(address < 1 << 26, "address is 26 bits");
+        Self((op as u32) << 26 | address)
+    }
 }
 
 #[macro_export]
@@ -79,7 +107,7 @@ repr_impl! {
     [#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]]
     pub enum InstKind(u8) {
         Special = 0x00,
-        Bal = 0x01,
+        RegImm = 0x01,
         J = 0x02,
         Jal = 0x03,
         Beq = 0x04,
@@ -94,18 +122,29 @@ repr_impl! {
         OrI = 0x0d,
         XorI = 0x0e,
         LUI = 0x0f,
-        Mfc0 = 0x10,
+        Cop0 = 0x10,
+        Cop1 = 0x11,
+        Special2 = 0x1c,
+        Special3 = 0x1f,
         LB = 0x20,
+        LH = 0x21,
+        LWL = 0x22,
         LW = 0x23,
         LBU = 0x24,
         LHU = 0x25,
+        LWR = 0x26,
         SB = 0x28,
         SH = 0x29,
+        SWL = 0x2a,
         SW = 0x2b,
+        SWR = 0x2e,
         Cache = 0x2f,
         LL = 0x30,
-        Lwci = 0x31,
+        Lwc1 = 0x31,
+        Ldc1 = 0x35,
         Sc = 0x38,
+        Swc1 = 0x39,
+        Sdc1 = 0x3d,
     }
 }
 
@@ -121,8 +160,12 @@ repr_impl! {
 
         Jr = 0x08,
         Jalr = 0x09,
+        Movz = 0x0a,
+        Movn = 0x0b,
 
         Syscall = 0x0c,
+        Break = 0x0d,
+        Sync = 0x0f,
 
         Mfhi = 0x10,
         Mthi = 0x11,
@@ -145,6 +188,137 @@ repr_impl! {
 
         Slt = 0x2a,
         Sltu = 0x2b,
+
+        Tge = 0x30,
+        Tgeu = 0x31,
+        Tlt = 0x32,
+        Tltu = 0x33,
+        Teq = 0x34,
+        Tne = 0x36,
+    }
+}
+
+// The REGIMM opcode group, selected by the `rt` field
+repr_impl! {
+    [#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]]
+    pub enum RegImm(u8) {
+        Bltz = 0x00,
+        Bgez = 0x01,
+        Bltzl = 0x02,
+        Bgezl = 0x03,
+
+        Tgei = 0x08,
+        Tgeiu = 0x09,
+        Tlti = 0x0a,
+        Tltiu = 0x0b,
+        Teqi = 0x0c,
+        Tnei = 0x0e,
+
+        Bltzal = 0x10,
+        Bgezal = 0x11,
+        Bltzall = 0x12,
+        Bgezall = 0x13,
+    }
+}
+
+// The SPECIAL2 opcode group, selected by `func`
+repr_impl! {
+    [#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]]
+    pub enum Special2(u8) {
+        Madd = 0x00,
+        MaddU = 0x01,
+        Mul = 0x02,
+        Msub = 0x04,
+        MsubU = 0x05,
+        Clz = 0x20,
+        Clo = 0x21,
+    }
+}
+
+// The SPECIAL3 opcode group, selected by `func`
+repr_impl! {
+    [#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]]
+    pub enum Special3(u8) {
+        Ext = 0x00,
+        Ins = 0x04,
+        Bshfl = 0x20,
+    }
+}
+
+// Byte and halfword shuffles in the BSHFL group, selected by the `shift` field
+repr_impl! {
+    [#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]]
+    pub enum Bshfl(u8) {
+        Wsbh = 0x02,
+        Seb = 0x10,
+        Seh = 0x18,
+    }
+}
+
+// The COP0 opcode group, selected by the `rs` field
+repr_impl! {
+    [#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]]
+    pub enum Cop0Op(u8) {
+        Mf = 0x00,
+        Mt = 0x04,
+        Co = 0x10,
+    }
+}
+
+/// `func` of `eret` in the `CO` group
+pub const ERET: u8 = 0x18;
+
+// The COP1 opcode group is selected by the `rs` field, which is the format for arithmetic
+repr_impl! {
+    [#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]]
+    pub enum Fmt(u8) {
+        Mf = 0x00,
+        Mt = 0x04,
+        Bc = 0x08,
+        S = 0x10,
+        D = 0x11,
+        W = 0x14,
+    }
+}
+
+// COP1 arithmetic, selected by `func` once the format is known
+repr_impl! {
+    [#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]]
+    pub enum FpuFunc(u8) {
+        Add = 0x00,
+        Sub = 0x01,
+        Mul = 0x02,
+        Div = 0x03,
+        Sqrt = 0x04,
+        Abs = 0x05,
+        Mov = 0x06,
+        Neg = 0x07,
+
+        RoundW = 0x0c,
+        TruncW = 0x0d,
+        CeilW = 0x0e,
+        FloorW = 0x0f,
+
+        CvtS = 0x20,
+        CvtD = 0x21,
+        CvtW = 0x24,
+
+        CF = 0x30,
+        CUn = 0x31,
+        CEq = 0x32,
+        CUeq = 0x33,
+        COlt = 0x34,
+        CUlt = 0x35,
+        COle = 0x36,
+        CUle = 0x37,
+        CSf = 0x38,
+        CNgle = 0x39,
+        CSeq = 0x3a,
+        CNgl = 0x3b,
+        CLt = 0x3c,
+        CNge = 0x3d,
+        CLe = 0x3e,
+        CNgt = 0x3f,
     }
 }
 
@@ -194,6 +368,232 @@ repr_impl! {
     }
 }
 
+impl InstKind {
+    pub fn inst_name(self) -> &'static str {
+        match self {
+            InstKind::Special => "<special>",
+            InstKind::RegImm => "<regimm>",
+            InstKind::J => "j",
+            InstKind::Jal => "jal",
+            InstKind::Beq => "beq",
+            InstKind::Bne => "bne",
+            InstKind::Blez => "blez",
+            InstKind::Bgtz => "bgtz",
+            InstKind::AddI => "addi",
+            InstKind::AddIU => "addiu",
+            InstKind::SltI => "slti",
+            InstKind::SltIU => "sltiu",
+            InstKind::AndI => "andi",
+            InstKind::OrI => "ori",
+            InstKind::XorI => "xori",
+            InstKind::LUI => "lui",
+            InstKind::Cop0 => "<cop0>",
+            InstKind::Cop1 => "<cop1>",
+            InstKind::Special2 => "<special2>",
+            InstKind::Special3 => "<special3>",
+            InstKind::LB => "lb",
+            InstKind::LH => "lh",
+            InstKind::LWL => "lwl",
+            InstKind::LW => "lw",
+            InstKind::LBU => "lbu",
+            InstKind::LHU => "lhu",
+            InstKind::LWR => "lwr",
+            InstKind::SB => "sb",
+            InstKind::SH => "sh",
+            InstKind::SWL => "swl",
+            InstKind::SW => "sw",
+            InstKind::SWR => "swr",
+            InstKind::Cache => "cache",
+            InstKind::LL => "ll",
+            InstKind::Lwc1 => "lwc1",
+            InstKind::Ldc1 => "ldc1",
+            InstKind::Sc => "sc",
+            InstKind::Swc1 => "swc1",
+            InstKind::Sdc1 => "sdc1",
+        }
+    }
+}
+
+impl RegImm {
+    pub fn inst_name(self) -> &'static str {
+        match self {
+            RegImm::Bltz => "bltz",
+            RegImm::Bgez => "bgez",
+            RegImm::Bltzl => "bltzl",
+            RegImm::Bgezl => "bgezl",
+            RegImm::Tgei => "tgei",
+            RegImm::Tgeiu => "tgeiu",
+            RegImm::Tlti => "tlti",
+            RegImm::Tltiu => "tltiu",
+            RegImm::Teqi => "teqi",
+            RegImm::Tnei => "tnei",
+            RegImm::Bltzal => "bltzal",
+            RegImm::Bgezal => "bgezal",
+            RegImm::Bltzall => "bltzall",
+            RegImm::Bgezall => "bgezall",
+        }
+    }
+
+    /// Whether this is one of the trap immediate instructions rather than a branch
+    pub fn is_trap(self) -> bool {
+        (RegImm::Tgei as u8..=RegImm::Tnei as u8).contains(&(self as u8))
+    }
+
+    /// Whether the branch is taken for the value of `$rs`
+    pub fn taken(self, rs: i32) -> bool {
+        match self {
+            RegImm::Bltz | RegImm::Bltzl | RegImm::Bltzal | RegImm::Bltzall => rs < 0,
+            RegImm::Bgez | RegImm::Bgezl | RegImm::Bgezal | RegImm::Bgezall => rs >= 0,
+            _ => unreachable!("{:?} is a trap", self),
+        }
+    }
+
+    /// Whether the trap is taken for the value of `$rs`
+    pub fn trap(self, rs: u32, imm: i16) -> bool {
+        debug_assert!(self.is_trap(), "{:?} is a branch", self);
+        trap_condition(self as u8, rs, imm as i32 as u32)
+    }
+
+    /// Whether `$ra` is set to the return address
+    pub fn links(self) -> bool {
+        matches!(
+            self,
+            RegImm::Bltzal | RegImm::Bgezal | RegImm::Bltzall | RegImm::Bgezall
+        )
+    }
+
+    /// Whether the delay slot is skipped when the branch is not taken
+    pub fn likely(self) -> bool {
+        matches!(
+            self,
+            RegImm::Bltzl | RegImm::Bgezl | RegImm::Bltzall | RegImm::Bgezall
+        )
+    }
+}
+
+impl Special2 {
+    pub fn inst_name(self) -> &'static str {
+        match self {
+            Special2::Madd => "madd",
+            Special2::MaddU => "maddu",
+            Special2::Mul => "mul",
+            Special2::Msub => "msub",
+            Special2::MsubU => "msubu",
+            Special2::Clz => "clz",
+            Special2::Clo => "clo",
+        }
+    }
+}
+
+impl Bshfl {
+    pub fn inst_name(self) -> &'static str {
+        match self {
+            Bshfl::Wsbh => "wsbh",
+            Bshfl::Seb => "seb",
+            Bshfl::Seh => "seh",
+        }
+    }
+}
+
+impl Fmt {
+    /// The suffix used in mnemonics, `add.s`
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Fmt::S => "s",
+            Fmt::D => "d",
+            Fmt::W => "w",
+            Fmt::Mf | Fmt::Mt | Fmt::Bc => "",
+        }
+    }
+}
+
+impl FpuFunc {
+    /// The mnemonic without the format suffix
+    pub fn inst_name(self) -> &'static str {
+        match self {
+            FpuFunc::Add => "add",
+            FpuFunc::Sub => "sub",
+            FpuFunc::Mul => "mul",
+            FpuFunc::Div => "div",
+            FpuFunc::Sqrt => "sqrt",
+            FpuFunc::Abs => "abs",
+            FpuFunc::Mov => "mov",
+            FpuFunc::Neg => "neg",
+            FpuFunc::RoundW => "round.w",
+            FpuFunc::TruncW => "trunc.w",
+            FpuFunc::CeilW => "ceil.w",
+            FpuFunc::FloorW => "floor.w",
+            FpuFunc::CvtS => "cvt.s",
+            FpuFunc::CvtD => "cvt.d",
+            FpuFunc::CvtW => "cvt.w",
+            FpuFunc::CF => "c.f",
+            FpuFunc::CUn => "c.un",
+            FpuFunc::CEq => "c.eq",
+            FpuFunc::CUeq => "c.ueq",
+            FpuFunc::COlt => "c.olt",
+            FpuFunc::CUlt => "c.ult",
+            FpuFunc::COle => "c.ole",
+            FpuFunc::CUle => "c.ule",
+            FpuFunc::CSf => "c.sf",
+            FpuFunc::CNgle => "c.ngle",
+            FpuFunc::CSeq => "c.seq",
+            FpuFunc::CNgl => "c.ngl",
+            FpuFunc::CLt => "c.lt",
+            FpuFunc::CNge => "c.nge",
+            FpuFunc::CLe => "c.le",
+            FpuFunc::CNgt => "c.ngt",
+        }
+    }
+
+    /// The formats that the operation may be used with
+    pub fn formats(self) -> &'static [Fmt] {
+        match self {
+            FpuFunc::CvtS => &[Fmt::D, Fmt::W],
+            FpuFunc::CvtD => &[Fmt::S, Fmt::W],
+            _ => &[Fmt::S, Fmt::D],
+        }
+    }
+
+    /// The format of the result, `None` for comparisons which set a condition flag instead
+    pub fn result(self, fmt: Fmt) -> Option<Fmt> {
+        match self {
+            FpuFunc::RoundW
+            | FpuFunc::TruncW
+            | FpuFunc::CeilW
+            | FpuFunc::FloorW
+            | FpuFunc::CvtW => Some(Fmt::W),
+            FpuFunc::CvtS => Some(Fmt::S),
+            FpuFunc::CvtD => Some(Fmt::D),
+            _ if self.is_compare() => None,
+            _ => Some(fmt),
+        }
+    }
+
+    /// Whether this is one of the `c.cond` comparisons
+    pub fn is_compare(self) -> bool {
+        self as u8 >= FpuFunc::CF as u8
+    }
+
+    /// Whether `fs` is only used with `fd`, there is no `ft`
+    pub fn is_unary(self) -> bool {
+        !self.is_compare()
+            && !matches!(
+                self,
+                FpuFunc::Add | FpuFunc::Sub | FpuFunc::Mul | FpuFunc::Div
+            )
+    }
+
+    /// The result of a `c.cond` comparison, the low bits of the condition select which of
+    /// unordered, equal and less than are true.  The signalling forms behave the same here
+    /// since invalid operation exceptions are not raised.
+    pub fn compare(self, s: f64, t: f64) -> bool {
+        let cond = self as u8;
+        (cond & 0b001 != 0 && (s.is_nan() || t.is_nan()))
+            || (cond & 0b010 != 0 && s == t)
+            || (cond & 0b100 != 0 && s < t)
+    }
+}
+
 impl Func {
     pub fn inst_name(self) -> &'static str {
         match self {
@@ -205,7 +605,11 @@ impl Func {
             Func::Srav => "srav",
             Func::Jr => "jr",
             Func::Jalr => "jalr",
-            Func::Syscall => "sycall",
+            Func::Movz => "movz",
+            Func::Movn => "movn",
+            Func::Syscall => "syscall",
+            Func::Break => "break",
+            Func::Sync => "sync",
             Func::Mfhi => "mfhi",
             Func::Mthi => "mthi",
             Func::Mflo => "mflo",
@@ -224,8 +628,33 @@ impl Func {
             Func::Nor => "nor",
             Func::Slt => "slt",
             Func::Sltu => "sltu",
+            Func::Tge => "tge",
+            Func::Tgeu => "tgeu",
+            Func::Tlt => "tlt",
+            Func::Tltu => "tltu",
+            Func::Teq => "teq",
+            Func::Tne => "tne",
         }
     }
+
+    /// Whether this is one of the conditional trap instructions
+    pub fn is_trap(self) -> bool {
+        self as u8 >= Func::Tge as u8
+    }
+}
+
+/// Whether a trap is taken, `code` is the `func` of a register trap or the `rt` of an
+/// immediate trap.  Both encode the comparison in the low 3 bits, and the immediate of
+/// `tgeiu`/`tltiu` is sign extended before the unsigned comparison.
+pub fn trap_condition(code: u8, s: u32, t: u32) -> bool {
+    match code & 0b111 {
+        0 => s as i32 >= t as i32,
+        1 => s >= t,
+        2 => (s as i32) < t as i32,
+        3 => s < t,
+        4 => s == t,
+        _ => s != t,
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
@@ -258,6 +687,117 @@ impl Inst {
         })
     }
 
+    /// `func $rd, $rs, $rt` (or `func $rd, $rt, shamt` for shifts)
+    pub fn r_type(func: Func, rd: u8, rs: u8, rt: u8, shamt: u8) -> Self {
+        Self {
+            kind: InstKind::Special,
+            opcode: Opcode::encode(InstKind::Special as u8, rs, rt, rd, shamt, func as u8),
+        }
+    }
+
+    /// `kind $rt, $rs, imm`
+    pub fn i_type(kind: InstKind, rt: u8, rs: u8, imm: i16) -> Self {
+        Self {
+            kind,
+            opcode: Opcode::encode_imm(kind as u8, rs, rt, imm),
+        }
+    }
+
+    /// `op $rs, offset`
+    pub fn regimm_type(op: RegImm, rs: u8, offset: i16) -> Self {
+        Self {
+            kind: InstKind::RegImm,
+            opcode: Opcode::encode_imm(InstKind::RegImm as u8, rs, op as u8, offset),
+        }
+    }
+
+    /// `func $rd, $rs, $rt`, for `madd` and friends `$rd` is unused
+    pub fn special2_type(func: Special2, rd: u8, rs: u8, rt: u8) -> Self {
+        Self {
+            kind: InstKind::Special2,
+            opcode: Opcode::encode(InstKind::Special2 as u8, rs, rt, rd, 0, func as u8),
+        }
+    }
+
+    /// `ext`/`ins $rt, $rs, pos, size`, the field bounds are encoded in `rd` and `shift`
+    pub fn bit_field_type(func: Special3, rt: u8, rs: u8, pos: u8, size: u8) -> Self {
+        debug_assert!(size > 0 && pos + size <= 32, "the field must be inside of a word");
+        let msb = match func {
+            // ext stores the size and ins the most significant bit
+            Special3::Ext => size - 1,
+            Special3::Ins => pos + size - 1,
+            Special3::Bshfl => unreachable!("bshfl is not a bit field instruction"),
+        };
+        Self {
+            kind: InstKind::Special3,
+            opcode: Opcode::encode(InstKind::Special3 as u8, rs, rt, msb, pos, func as u8),
+        }
+    }
+
+    /// `op $rd, $rt`
+    pub fn bshfl_type(op: Bshfl, rd: u8, rt: u8) -> Self {
+        Self {
+            kind: InstKind::Special3,
+            opcode: Opcode::encode(
+                InstKind::Special3 as u8,
+                0,
+                rt,
+                rd,
+                op as u8,
+                Special3::Bshfl as u8,
+            ),
+        }
+    }
+
+    /// `mfc0`/`mtc0 $rt, $rd`
+    pub fn cop0_move(op: Cop0Op, rt: u8, rd: u8) -> Self {
+        Self {
+            kind: InstKind::Cop0,
+            opcode: Opcode::encode(InstKind::Cop0 as u8, op as u8, rt, rd, 0, 0),
+        }
+    }
+
+    pub fn eret() -> Self {
+        Self {
+            kind: InstKind::Cop0,
+            opcode: Opcode::encode(InstKind::Cop0 as u8, Cop0Op::Co as u8, 0, 0, 0, ERET),
+        }
+    }
+
+    /// `func.fmt fd, fs, ft`
+    pub fn fpu_type(func: FpuFunc, fmt: Fmt, fd: u8, fs: u8, ft: u8) -> Self {
+        Self {
+            kind: InstKind::Cop1,
+            opcode: Opcode::encode(InstKind::Cop1 as u8, fmt as u8, ft, fs, fd, func as u8),
+        }
+    }
+
+    /// `mfc1`/`mtc1 $rt, fs`
+    pub fn fpu_move(fmt: Fmt, rt: u8, fs: u8) -> Self {
+        Self {
+            kind: InstKind::Cop1,
+            opcode: Opcode::encode(InstKind::Cop1 as u8, fmt as u8, rt, fs, 0, 0),
+        }
+    }
+
+    /// `bc1t`/`bc1f cc, offset`, `likely` skips the delay slot when the branch is not taken
+    pub fn fpu_branch(cc: u8, likely: bool, on_true: bool, offset: i16) -> Self {
+        debug_assert!(cc < 8, "there are 8 condition flags");
+        let rt = cc << 2 | (likely as u8) << 1 | on_true as u8;
+        Self {
+            kind: InstKind::Cop1,
+            opcode: Opcode::encode_imm(InstKind::Cop1 as u8, Fmt::Bc as u8, rt, offset),
+        }
+    }
+
+    /// `kind index`, where `index` is the 26 bit instruction index of the target
+    pub fn j_type(kind: InstKind, index: u32) -> Self {
+        Self {
+            kind,
+            opcode: Opcode::encode_jump(kind as u8, index),
+        }
+    }
+
     pub fn reg(self) -> Reg {
         Reg {
             rs: self.opcode.rs(),
@@ -280,10 +820,138 @@ impl Inst {
         self.opcode.address()
     }
 
+    /// The target of a `j`/`jal`, the instruction index replaces the low 28 bits of the address
+    /// of the delay slot, so jumps stay within the current 256 MiB region
+    pub fn jump_target(self, delay_slot: usize) -> usize {
+        (delay_slot & 0xf000_0000) | (self.jmp() as usize) << 2
+    }
+
+    /// The 10 bit code of a trap, which is ignored by the processor but may be read by an
+    /// exception handler.  `break` has another 10 bit code above this one.
+    pub fn code(self) -> u32 {
+        (self.opcode.0 >> 6) & 0x3ff
+    }
+
     pub fn func(self) -> Option<Func> {
         Func::new(self.opcode.func())
     }
 
+    pub fn regimm(self) -> Option<RegImm> {
+        RegImm::new(self.opcode.rt())
+    }
+
+    pub fn special2(self) -> Option<Special2> {
+        Special2::new(self.opcode.func())
+    }
+
+    pub fn special3(self) -> Option<Special3> {
+        Special3::new(self.opcode.func())
+    }
+
+    pub fn bshfl(self) -> Option<Bshfl> {
+        Bshfl::new(self.opcode.shift())
+    }
+
+    /// The `(pos, size)` of the field that an `ext` or `ins` works on
+    pub fn bit_field(self) -> (u8, u8) {
+        let pos = self.opcode.shift();
+        let msb = self.opcode.rd();
+        match self.special3() {
+            Some(Special3::Ins) => (pos, (msb + 1).saturating_sub(pos)),
+            _ => (pos, msb + 1),
+        }
+    }
+
+    /// The general purpose register that it writes, if any.  `nop`, `ssnop` and `ehb` are
+    /// shifts of `$zero` into `$zero`, those don't count.
+    pub fn dest(self) -> Option<u8> {
+        let Reg { rs, rt, rd, .. } = self.reg();
+        match self.kind {
+            InstKind::Special => match self.func()? {
+                Func::Sll if rt == 0 => None,
+                Func::Jr
+                | Func::Syscall
+                | Func::Break
+                | Func::Sync
+                | Func::Mthi
+                | Func::Mtlo
+                | Func::Mult
+                | Func::MultU
+                | Func::Div
+                | Func::DivU
+                | Func::Tge
+                | Func::Tgeu
+                | Func::Tlt
+                | Func::Tltu
+                | Func::Teq
+                | Func::Tne => None,
+                _ => Some(rd),
+            },
+            InstKind::RegImm => self.regimm()?.links().then_some(31),
+            InstKind::Jal => Some(31),
+            InstKind::AddI
+            | InstKind::AddIU
+            | InstKind::SltI
+            | InstKind::SltIU
+            | InstKind::AndI
+            | InstKind::OrI
+            | InstKind::XorI
+            | InstKind::LUI
+            | InstKind::LB
+            | InstKind::LH
+            | InstKind::LWL
+            | InstKind::LW
+            | InstKind::LBU
+            | InstKind::LHU
+            | InstKind::LWR
+            | InstKind::LL
+            | InstKind::Sc => Some(rt),
+            InstKind::Special2 => match self.special2()? {
+                Special2::Mul | Special2::Clz | Special2::Clo => Some(rd),
+                _ => None,
+            },
+            InstKind::Special3 => match self.special3()? {
+                Special3::Ext | Special3::Ins => Some(rt),
+                Special3::Bshfl => Some(rd),
+            },
+            InstKind::Cop0 => (Cop0Op::new(rs)? == Cop0Op::Mf).then_some(rt),
+            InstKind::Cop1 => (Fmt::new(rs)? == Fmt::Mf).then_some(rt),
+            _ => None,
+        }
+    }
+
+    pub fn cop0_op(self) -> Option<Cop0Op> {
+        Cop0Op::new(self.opcode.rs())
+    }
+
+    pub fn fmt(self) -> Option<Fmt> {
+        Fmt::new(self.opcode.rs())
+    }
+
+    pub fn fpu_func(self) -> Option<FpuFunc> {
+        FpuFunc::new(self.opcode.func())
+    }
+
+    /// The full mnemonic of a COP1 instruction, since these are made of the operation and its
+    /// format (`cvt.s.d`) they are built rather than looked up
+    pub fn fpu_name(self) -> String {
+        match self.fmt() {
+            Some(Fmt::Mf) => "mfc1".into(),
+            Some(Fmt::Mt) => "mtc1".into(),
+            Some(Fmt::Bc) => {
+                let rt = self.opcode.rt();
+                let tf = if rt & 1 != 0 { "t" } else { "f" };
+                let likely = if rt & 2 != 0 { "l" } else { "" };
+                format!("bc1{}{}", tf, likely)
+            }
+            Some(fmt) => match self.fpu_func() {
+                Some(func) => format!("{}.{}", func.inst_name(), fmt.suffix()),
+                None => "<unknown cop1 opcode>".into(),
+            },
+            None => "<unknown cop1 format>".into(),
+        }
+    }
+
     pub fn inst_name(self) -> &'static str {
         match self.kind {
             InstKind::Special => {
@@ -293,33 +961,256 @@ impl Inst {
                     "<unknown special opcode>"
                 }
             }
-            InstKind::Bal => "bal",
-            InstKind::J => "j",
-            InstKind::Jal => "jal",
-            InstKind::Beq => "beq",
-            InstKind::Bne => "bne",
-            InstKind::Blez => "blez",
-            InstKind::Bgtz => "bgtz",
-            InstKind::AddI => "addi",
-            InstKind::AddIU => "addiu",
-            InstKind::SltI => "slti",
-            InstKind::SltIU => "sltiu",
-            InstKind::AndI => "andi",
-            InstKind::OrI => "ori",
-            InstKind::XorI => "xori",
-            InstKind::LUI => "lui",
-            InstKind::Mfc0 => "mfc0",
-            InstKind::LB => "lb",
-            InstKind::LW => "lw",
-            InstKind::LBU => "lbu",
-            InstKind::LHU => "lhu",
-            InstKind::SB => "sb",
-            InstKind::SH => "sh",
-            InstKind::SW => "sw",
-            InstKind::Cache => "<Cache OP>",
-            InstKind::LL => "ll",
-            InstKind::Lwci => "lwci",
-            InstKind::Sc => "sc",
+            InstKind::RegImm => match self.regimm() {
+                // unconditional forms that use $zero
+                Some(RegImm::Bgez) if self.opcode.rs() == 0 => "b",
+                Some(RegImm::Bgezal) if self.opcode.rs() == 0 => "bal",
+                Some(r) => r.inst_name(),
+                None => "<unknown regimm opcode>",
+            },
+            InstKind::Special2 => match self.special2() {
+                Some(f) => f.inst_name(),
+                None => "<unknown special2 opcode>",
+            },
+            InstKind::Special3 => match self.special3() {
+                Some(Special3::Ext) => "ext",
+                Some(Special3::Ins) => "ins",
+                Some(Special3::Bshfl) => match self.bshfl() {
+                    Some(op) => op.inst_name(),
+                    None => "<unknown bshfl opcode>",
+                },
+                None => "<unknown special3 opcode>",
+            },
+            InstKind::Cop0 => match self.cop0_op() {
+                Some(Cop0Op::Mf) => "mfc0",
+                Some(Cop0Op::Mt) => "mtc0",
+                Some(Cop0Op::Co) if self.opcode.func() == ERET => "eret",
+                _ => "<unknown cop0 opcode>",
+            },
+            kind => kind.inst_name(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    // every variant of an opcode group, found by trying each value of its field
+    fn all<T>(new: fn(u8) -> Option<T>) -> Vec<T> {
+        (0..=u8::MAX).filter_map(new).collect()
+    }
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(0x6e67)
+    }
+
+    fn reg(rng: &mut StdRng) -> u8 {
+        rng.gen_range(0..32)
+    }
+
+    // decoding what an encoder built must give back the same instruction
+    fn round_trip(inst: Inst) {
+        assert_eq!(Inst::new(inst.opcode), Some(inst), "{:08x}", inst.opcode.0);
+    }
+
+    #[test]
+    fn r_type() {
+        let mut rng = rng();
+        for func in all(Func::new) {
+            for _ in 0..64 {
+                let (rd, rs, rt, shamt) =
+                    (reg(&mut rng), reg(&mut rng), reg(&mut rng), reg(&mut rng));
+                let inst = Inst::r_type(func, rd, rs, rt, shamt);
+                round_trip(inst);
+                assert_eq!(inst.kind, InstKind::Special);
+                assert_eq!(inst.func(), Some(func));
+                let r = inst.reg();
+                assert_eq!((r.rd, r.rs, r.rt, r.shift), (rd, rs, rt, shamt));
+            }
+        }
+    }
+
+    #[test]
+    fn i_type() {
+        let mut rng = rng();
+        // the kinds that are not a group of their own or a jump
+        let kinds = all(InstKind::new).into_iter().filter(|kind| {
+            !matches!(
+                kind,
+                InstKind::Special
+                    | InstKind::RegImm
+                    | InstKind::J
+                    | InstKind::Jal
+                    | InstKind::Cop0
+                    | InstKind::Cop1
+                    | InstKind::Special2
+                    | InstKind::Special3
+            )
+        });
+        for kind in kinds {
+            for _ in 0..64 {
+                let (rt, rs, imm) = (reg(&mut rng), reg(&mut rng), rng.gen());
+                let inst = Inst::i_type(kind, rt, rs, imm);
+                round_trip(inst);
+                assert_eq!(inst.kind, kind);
+                assert_eq!(inst.imm(), Imm { rs, rt, imm });
+            }
+        }
+    }
+
+    #[test]
+    fn j_type() {
+        let mut rng = rng();
+        for kind in [InstKind::J, InstKind::Jal] {
+            for _ in 0..64 {
+                let index = rng.gen_range(0..1 << 26);
+                let inst = Inst::j_type(kind, index);
+                round_trip(inst);
+                assert_eq!(inst.kind, kind);
+                assert_eq!(inst.jmp(), index as i32);
+            }
+        }
+    }
+
+    #[test]
+    fn jump_target_is_in_the_region_of_the_delay_slot() {
+        let j = Inst::j_type(InstKind::J, 0x40);
+        // the last word of a 256 MiB region jumps into the next one, its delay slot is there
+        assert_eq!(j.jump_target(0x0fff_fffc + 4), 0x1000_0100);
+        assert_eq!(j.jump_target(0x0fff_fff8 + 4), 0x0000_0100);
+        // the top of the index reaches the end of the region
+        let j = Inst::j_type(InstKind::Jal, (1 << 26) - 1);
+        assert_eq!(j.jump_target(0x8000_0000 + 4), 0x8fff_fffc);
+    }
+
+    #[test]
+    fn regimm_type() {
+        let mut rng = rng();
+        for op in all(RegImm::new) {
+            for _ in 0..64 {
+                let (rs, offset) = (reg(&mut rng), rng.gen());
+                let inst = Inst::regimm_type(op, rs, offset);
+                round_trip(inst);
+                assert_eq!(inst.regimm(), Some(op));
+                assert_eq!((inst.imm().rs, inst.imm().imm), (rs, offset));
+            }
+        }
+    }
+
+    #[test]
+    fn special2_type() {
+        let mut rng = rng();
+        for func in all(Special2::new) {
+            for _ in 0..64 {
+                let (rd, rs, rt) = (reg(&mut rng), reg(&mut rng), reg(&mut rng));
+                let inst = Inst::special2_type(func, rd, rs, rt);
+                round_trip(inst);
+                assert_eq!(inst.special2(), Some(func));
+                let r = inst.reg();
+                assert_eq!((r.rd, r.rs, r.rt), (rd, rs, rt));
+            }
+        }
+    }
+
+    #[test]
+    fn bit_field_type() {
+        let mut rng = rng();
+        for func in [Special3::Ext, Special3::Ins] {
+            for _ in 0..256 {
+                let (rt, rs) = (reg(&mut rng), reg(&mut rng));
+                let pos = rng.gen_range(0..32);
+                let size = rng.gen_range(1..=32 - pos);
+                let inst = Inst::bit_field_type(func, rt, rs, pos, size);
+                round_trip(inst);
+                assert_eq!(inst.special3(), Some(func));
+                assert_eq!(inst.bit_field(), (pos, size));
+                assert_eq!((inst.imm().rt, inst.imm().rs), (rt, rs));
+            }
+        }
+    }
+
+    #[test]
+    fn bshfl_type() {
+        let mut rng = rng();
+        for op in all(Bshfl::new) {
+            for _ in 0..64 {
+                let (rd, rt) = (reg(&mut rng), reg(&mut rng));
+                let inst = Inst::bshfl_type(op, rd, rt);
+                round_trip(inst);
+                assert_eq!(inst.special3(), Some(Special3::Bshfl));
+                assert_eq!(inst.bshfl(), Some(op));
+                assert_eq!((inst.reg().rd, inst.reg().rt), (rd, rt));
+            }
+        }
+    }
+
+    #[test]
+    fn cop0() {
+        let mut rng = rng();
+        for op in [Cop0Op::Mf, Cop0Op::Mt] {
+            for _ in 0..64 {
+                let (rt, rd) = (reg(&mut rng), reg(&mut rng));
+                let inst = Inst::cop0_move(op, rt, rd);
+                round_trip(inst);
+                assert_eq!(inst.cop0_op(), Some(op));
+                assert_eq!((inst.reg().rt, inst.reg().rd), (rt, rd));
+            }
+        }
+        let eret = Inst::eret();
+        round_trip(eret);
+        assert_eq!(eret.cop0_op(), Some(Cop0Op::Co));
+        assert_eq!(eret.inst_name(), "eret");
+    }
+
+    #[test]
+    fn fpu_type() {
+        let mut rng = rng();
+        for func in all(FpuFunc::new) {
+            for fmt in [Fmt::S, Fmt::D, Fmt::W] {
+                for _ in 0..16 {
+                    let (fd, fs, ft) = (reg(&mut rng), reg(&mut rng), reg(&mut rng));
+                    let inst = Inst::fpu_type(func, fmt, fd, fs, ft);
+                    round_trip(inst);
+                    assert_eq!((inst.fmt(), inst.fpu_func()), (Some(fmt), Some(func)));
+                    let r = inst.reg();
+                    assert_eq!((r.shift, r.rd, r.rt), (fd, fs, ft));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fpu_move_and_branch() {
+        let mut rng = rng();
+        for fmt in [Fmt::Mf, Fmt::Mt] {
+            for _ in 0..64 {
+                let (rt, fs) = (reg(&mut rng), reg(&mut rng));
+                let inst = Inst::fpu_move(fmt, rt, fs);
+                round_trip(inst);
+                assert_eq!(inst.fmt(), Some(fmt));
+                assert_eq!((inst.reg().rt, inst.reg().rd), (rt, fs));
+            }
+        }
+        for cc in 0..8 {
+            for likely in [false, true] {
+                for on_true in [false, true] {
+                    let offset = rng.gen();
+                    let inst = Inst::fpu_branch(cc, likely, on_true, offset);
+                    round_trip(inst);
+                    assert_eq!(inst.fmt(), Some(Fmt::Bc));
+                    assert_eq!(inst.opcode.rt() >> 2, cc);
+                    assert_eq!(inst.imm().imm, offset);
+                    let name = format!(
+                        "bc1{}{}",
+                        if on_true { "t" } else { "f" },
+                        if likely { "l" } else { "" }
+                    );
+                    assert_eq!(inst.fpu_name(), name);
+                }
+            }
         }
     }
 }
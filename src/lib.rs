@@ -0,0 +1,1324 @@
+#[macro_use]
+pub mod inst;
+pub mod asm;
+pub mod cop0;
+pub mod decomp;
+pub mod fpu;
+pub mod input;
+pub mod loader;
+pub mod mem;
+pub mod memmap;
+pub mod mmio;
+pub mod reg;
+pub mod tui;
+
+use std::{
+    borrow::BorrowMut,
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    fs::File,
+    io::{Read, Write as _},
+    ops::{Index, IndexMut},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use asm::SourceLine;
+use clap::ValueEnum;
+use cop0::{aligned, overflow, ExcCode, Exception, Fault, SyscallError};
+use decomp::{Decomp, DecompKind};
+use elf::{endian::LittleEndian, section::SectionHeader, ElfBytes};
+use inst::{
+    trap_condition, Bshfl, Func, Imm, Inst, InstKind, Opcode, Reg, Special2, Special3, Syscall,
+};
+use mem::{Memory, Perms};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use reg::*;
+
+#[derive(Copy, Clone, Eq, PartialEq, PartialOrd, Ord)]
+pub enum InstructionResult {
+    None,
+    Done,
+    Exit(u32),
+}
+
+/// What `div` and `divu` do with a zero divisor, the result is unpredictable on MIPS.
+///
+/// `i32::MIN / -1` is not covered by this, it always gives `i32::MIN` with a remainder of zero
+/// like MARS, since neither `div` nor `divu` raise exceptions for overflow.
+#[derive(ValueEnum, Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum DivPolicy {
+    /// Leave `hi` and `lo` unchanged, like MARS
+    #[default]
+    Mars,
+    /// Set `hi` and `lo` to zero
+    Zero,
+    /// Raise a trap exception, like the `teq` that compilers put after a division
+    Trap,
+}
+
+repr_impl! {
+    [#[derive(Copy, Clone, Debug)]]
+    pub enum FileFlags(u32) {
+        ReadOnly = 0,
+        WriteOnlyCreate = 1,
+        WriteOnlyAppend = 9,
+    }
+}
+
+macro_rules! index {
+    ($ident: ident.$field: ident[$($kind: ident),+]) => {
+        $(
+        impl Index<$kind> for $ident {
+            type Output = u32;
+
+            fn index(&self, index: $kind) -> &Self::Output {
+                &self.$field[index as usize]
+            }
+        }
+
+        impl IndexMut<$kind> for $ident {
+            fn index_mut(&mut self, index: $kind) -> &mut Self::Output {
+                &mut self.$field[index as usize]
+            }
+        }
+        )+
+    };
+}
+
+impl FileFlags {
+    pub fn open_file(self, file: &str) -> Option<File> {
+        match self {
+            FileFlags::ReadOnly => File::open(file).ok(),
+            FileFlags::WriteOnlyCreate => File::create_new(file).ok(),
+            FileFlags::WriteOnlyAppend => File::create(file).ok(),
+        }
+    }
+}
+
+impl Iterator for Greg {
+    type Item = Inst;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ip == self.memory.text.1 {
+            return None;
+        }
+        let inst = self.fetch().ok()?;
+        self.ip += 4;
+        Some(inst)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DebugInfo {
+    // string: addr
+    labels: HashMap<String, usize>,
+    // first addr: source line, only for programs assembled by greg
+    source: BTreeMap<usize, SourceLine>,
+}
+
+impl DebugInfo {
+    pub fn from(elf: &ElfBytes<'_, LittleEndian>, text: &SectionHeader) -> Self {
+        let mut labels = HashMap::new();
+        let Ok(Some((symtab, strtab))) = elf.symbol_table() else {
+            return Self {
+                labels,
+                source: BTreeMap::new(),
+            };
+        };
+        // dbg!(text.sh_addr, text.sh_addr + text.sh_size);
+        // symbols with a name that can't be read are skipped, they are only used for display
+        for (name, sym) in symtab
+            .iter()
+            .filter_map(|sym| Some((strtab.get(sym.st_name as usize).ok()?, sym)))
+        {
+            if sym.st_value >= text.sh_addr
+                && sym.st_value <= text.sh_addr + text.sh_size
+                && !name.is_empty()
+                && (!name.starts_with('_') || name == "__start")
+            {
+                labels.insert(name.to_string(), sym.st_value as usize);
+            }
+        }
+        Self {
+            labels,
+            source: BTreeMap::new(),
+        }
+    }
+
+    /// The line of source that the instruction at `addr` was assembled from
+    pub fn source_at(&self, addr: usize) -> Option<&SourceLine> {
+        self.source
+            .range(..=addr)
+            .next_back()
+            .map(|(_, line)| line)
+            .filter(|line| line.addrs.contains(&addr))
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Greg {
+    // TODO: This should probably be i32 and cast to u32 when needing to do unsigned ops
+    pub reg: [u32; 32],
+    // Coprocessor 1, kept as raw bits since doubles are split over a pair of registers
+    pub freg: [u32; 32],
+    // Floating point control and status, holds the condition flags and rounding mode
+    pub fcsr: u32,
+    // Coprocessor 0, the exception state
+    pub cop0: [u32; 32],
+    // Where exceptions are handled, `cop0::EXCEPTION_HANDLER` unless it is changed
+    pub handler: Option<usize>,
+    // TODO: Dynamic memory
+    pub memory: Memory,
+    pub ip: usize,
+    // Using a hashmap since we can close files - could also do Vec<Option<File>> but that is more
+    // work than I want to do currently
+    // fd: File
+    pub open_files: HashMap<u32, File>,
+    pub rngs: HashMap<u32, StdRng>,
+
+    pub hi: u32,
+    pub lo: u32,
+
+    // Only included if the binary was compiled with debug info (`-ggdb` on gcc)
+    pub debug: Option<DebugInfo>,
+
+    // If Some(_), then write stdout here,
+    // otherwise, print it to stdout
+    pub stdout: Option<String>,
+
+    // Run the instruction after a branch or jump before taking it, like real hardware does.
+    // MARS has this disabled by default.
+    pub delay_slots: bool,
+    // Target of a branch that is waiting on its delay slot
+    pub branch: Option<usize>,
+    // Report instructions that write to $zero, the write is always discarded
+    pub warn_zero: bool,
+    // What dividing by zero does
+    pub div_by_zero: DivPolicy,
+    // Allow any access to mapped memory, for self-modifying code
+    pub no_protect: bool,
+}
+
+index!(Greg.reg[usize, u64, u32, u16, u8]);
+
+impl Greg {
+    /// The word at `ip`, if it is in executable memory
+    fn word_at(&self, ip: usize) -> Option<u32> {
+        let exec = |perms: Perms| perms.exec || self.no_protect;
+        if !ip.is_multiple_of(4) || !self.memory.perms(ip).is_some_and(exec) {
+            return None;
+        }
+        self.memory.get_u32(ip)
+    }
+
+    /// Fetch and decode the instruction at `ip`
+    fn fetch(&self) -> Result<Inst, Exception> {
+        let ip = aligned(self.ip, 4, ExcCode::AdEL)?;
+        let word = self
+            .word_at(ip)
+            .ok_or(Exception::address(ExcCode::AdEL, ip))?;
+        Inst::new(Opcode(word)).ok_or(Exception::new(ExcCode::RI))
+    }
+
+    // Loads and stores of memory that is not mapped, or that its segment doesn't permit, are
+    // address errors like in MARS
+
+    /// Check that all of `addr..addr + len` may be loaded from (`AdEL`) or stored to (`AdES`)
+    fn check(&self, addr: usize, len: usize, code: ExcCode) -> Result<(), Exception> {
+        let store = code == ExcCode::AdES;
+        let allowed = |perms: Perms| self.no_protect || if store { perms.write } else { perms.read };
+        // the address error is for the first byte that can't be accessed
+        match self.memory.unmapped_with(addr, len, allowed) {
+            Some(bad) => Err(Exception::address(code, bad)),
+            None => Ok(()),
+        }
+    }
+
+    fn load_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, Exception> {
+        self.check(addr, len, ExcCode::AdEL)?;
+        self.memory
+            .bytes(addr, len)
+            .ok_or(Exception::address(ExcCode::AdEL, addr))
+    }
+
+    fn load<const N: usize>(&self, addr: usize) -> Result<[u8; N], Exception> {
+        let mut buf = [0; N];
+        self.check(addr, N, ExcCode::AdEL)?;
+        self.memory
+            .read(addr, &mut buf)
+            .ok_or(Exception::address(ExcCode::AdEL, addr))?;
+        Ok(buf)
+    }
+
+    fn load_u8(&self, addr: usize) -> Result<u8, Exception> {
+        self.load(addr).map(u8::from_le_bytes)
+    }
+
+    fn load_u16(&self, addr: usize) -> Result<u16, Exception> {
+        self.load(addr).map(u16::from_le_bytes)
+    }
+
+    fn load_u32(&self, addr: usize) -> Result<u32, Exception> {
+        self.load(addr).map(u32::from_le_bytes)
+    }
+
+    fn store(&mut self, addr: usize, bytes: &[u8]) -> Result<(), Exception> {
+        self.check(addr, bytes.len(), ExcCode::AdES)?;
+        self.memory
+            .write(addr, bytes)
+            .ok_or(Exception::address(ExcCode::AdES, addr))
+    }
+
+    fn store_u8(&mut self, addr: usize, value: u8) -> Result<(), Exception> {
+        self.store(addr, &value.to_le_bytes())
+    }
+
+    fn store_u16(&mut self, addr: usize, value: u16) -> Result<(), Exception> {
+        self.store(addr, &value.to_le_bytes())
+    }
+
+    fn store_u32(&mut self, addr: usize, value: u32) -> Result<(), Exception> {
+        self.store(addr, &value.to_le_bytes())
+    }
+
+    /// The nul-terminated string at `addr`, with anything that isn't UTF-8 replaced
+    fn load_cstr(&self, addr: usize) -> Result<String, Exception> {
+        let Some(cstr) = self.memory.cstr(addr) else {
+            // it runs out of mapped memory, checking the rest of it finds where
+            self.check(addr, usize::MAX - addr, ExcCode::AdEL)?;
+            return Err(Exception::address(ExcCode::AdEL, addr));
+        };
+        self.check(addr, cstr.as_bytes_with_nul().len(), ExcCode::AdEL)?;
+        Ok(cstr.to_string_lossy().into_owned())
+    }
+
+    fn get_rng(&mut self, n: u32) -> &mut StdRng {
+        self.rngs
+            .entry(n)
+            .or_insert_with(|| StdRng::from_seed(Default::default()))
+            .borrow_mut()
+    }
+
+    pub fn syscall(&mut self) -> Result<InstructionResult, Exception> {
+        let Some(syscall) = Syscall::new(self[V0]) else {
+            return Err(Exception::syscall(SyscallError::Unknown));
+        };
+        macro_rules! print_write {
+            ($($arg:tt)*) => {{
+                if let Some(ref mut s) = self.stdout {
+                    write!(s, $($arg)*).expect("Write to string will never fail");
+                } else {
+                    print!($($arg)*);
+                }
+            }};
+        }
+        match syscall {
+            Syscall::PrintInteger => {
+                let n = self[A0] as i32;
+                print_write!("{}", n);
+            }
+            Syscall::PrintFloat => {
+                let f = self.get_f32(12);
+                print_write!("{}", fpu::java_float(f));
+            }
+            Syscall::PrintDouble => {
+                let f = self.get_f64(12)?;
+                print_write!("{}", fpu::java_float(f));
+            }
+            Syscall::PrintString => {
+                let s = self.load_cstr(self[A0] as usize)?;
+                print_write!("{}", s);
+            }
+            Syscall::ReadInteger => {
+                let n: i32 = parse_input(&read_line()?)?;
+                self[V0] = n as u32;
+            }
+            Syscall::ReadFloat => {
+                let f: f32 = parse_input(&read_line()?)?;
+                self.set_f32(0, f);
+            }
+            Syscall::ReadDouble => {
+                let f: f64 = parse_input(&read_line()?)?;
+                self.set_f64(0, f)?;
+            }
+            Syscall::ReadString => {
+                // $a0 = address of input buffer
+                // $a1 = maximum number of characters to read
+                // like MARS this reads at most $a1 - 1 characters, including the newline, and
+                // always terminates them with a nul
+                let addr = self[A0] as usize;
+                let max = self[A1] as i32;
+                if max > 0 {
+                    // at the end of input this reads an empty string
+                    let mut bytes = read_line().unwrap_or_default().into_bytes();
+                    bytes.truncate(max as usize - 1);
+                    bytes.push(0);
+                    self.store(addr, &bytes)?;
+                }
+            }
+            Syscall::Sbrk => {
+                // $a0 = number of bytes to allocate, MARS keeps the break word aligned
+                let bytes = self[A0] as i32;
+                if bytes < 0 {
+                    return Err(Exception::syscall(SyscallError::InvalidArgument));
+                }
+                let bytes = (bytes as usize).next_multiple_of(4);
+                let Some(addr) = self.memory.alloc(bytes) else {
+                    return Err(Exception::syscall(SyscallError::OutOfMemory));
+                };
+                self[V0] = addr as u32;
+            }
+            Syscall::Exit => {
+                print_write!("[syscall] exit with code 0");
+                return Ok(InstructionResult::Exit(0));
+            }
+            Syscall::PrintCharacter => {
+                let c = self[A0] as u8 as char;
+                print_write!("{}", c);
+            }
+            Syscall::ReadCharacter => {
+                let c = input::next().ok_or(Exception::syscall(SyscallError::EndOfInput))?;
+                self[V0] = c as u32;
+            }
+            Syscall::OpenFile => {
+                // TODO: max open files?
+                let file = self.load_cstr(self[A0] as usize)?;
+                // ignored in MARS
+                let _mode = self[A2];
+
+                // unknown flags fail to open, like a file that doesn't exist
+                let file = FileFlags::new(self[A1]).and_then(|flags| flags.open_file(&file));
+
+                self[V0] = if let Some(file) = file {
+                    // 0   - stdin
+                    // 1   - stdout
+                    // 2   - stderr
+                    // 3.. - open file
+                    let fd = self.open_files.len() as u32 + 3;
+                    self.open_files.insert(fd, file);
+                    fd
+                } else {
+                    (-1i32) as u32
+                };
+            }
+            Syscall::ReadFromFile => {
+                // $a0 = file descriptor
+                // $a1 = address of input buffer
+                // $a2 = maximum number of characters to read
+                let fd = self[A0];
+                let addr = self[A1] as usize;
+                let bytes = self[A2] as usize;
+                self.check(addr, bytes, ExcCode::AdES)?;
+                let Some(file) = self.open_files.get_mut(&fd) else {
+                    self[V0] = (-1i32) as u32;
+                    return Ok(InstructionResult::None);
+                };
+                // read into a buffer that grows with what is read, `bytes` may be huge
+                let mut buf = Vec::new();
+                match file.take(bytes as u64).read_to_end(&mut buf) {
+                    Ok(n) => {
+                        self.memory.write(addr, &buf);
+                        self[V0] = n as u32;
+                    }
+                    Err(e) => {
+                        dbg!(e);
+                        self[V0] = (-1i32) as u32;
+                    }
+                }
+            }
+            Syscall::WriteToFile => {
+                let fd = self[A0];
+                let buf = self[A1] as usize;
+                let len = self[A2] as usize;
+
+                let bytes = self.load_bytes(buf, len)?;
+                if let Some(mut file) = self.open_files.get(&fd) {
+                    match file.write(&bytes) {
+                        Ok(n) => self[V0] = n as u32,
+                        Err(_) => {
+                            // dbg!(e);
+                            self[V0] = (-1i32) as u32;
+                        }
+                    }
+                } else {
+                    self[V0] = (-1i32) as u32;
+                }
+            }
+            Syscall::CloseFile => {
+                let fd = self[A0];
+
+                if self.open_files.contains_key(&fd) {
+                    self.open_files.remove(&fd);
+                }
+            }
+            Syscall::Exit2 => {
+                let code = self[A0];
+                print_write!("[syscall] exit with explicit code {:?}", code);
+                return Ok(InstructionResult::Exit(code));
+            }
+            Syscall::Time => {
+                let time = SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default();
+                let secs = time.as_millis() as u64;
+
+                self[A0] = secs as u32;
+                self[A1] = (secs >> 32) as u32;
+            }
+            Syscall::MidiOut => return Err(Exception::syscall(SyscallError::Unimplemented)),
+            Syscall::Sleep => {
+                let dur = self[A0];
+                thread::sleep(Duration::from_millis(dur as u64));
+            }
+            Syscall::MidiOutSynchronous => {
+                return Err(Exception::syscall(SyscallError::Unimplemented))
+            }
+            Syscall::PrintHexInteger => {
+                let n = self[A0];
+                if let Some(ref mut s) = self.stdout {
+                    write!(s, "0x{:08x}", n).expect("Write to string will never fail");
+                } else {
+                    print!("0x{:08x}", n);
+                }
+            }
+            Syscall::PrintBinInteger => {
+                let n = self[A0];
+                if let Some(ref mut s) = self.stdout {
+                    write!(s, "0b{:032b}", n).expect("Write to string will never fail");
+                } else {
+                    print!("0b{:032b}", n);
+                }
+            }
+            Syscall::PrintUnsignedInteger => {
+                let n = self[A0];
+                if let Some(ref mut s) = self.stdout {
+                    write!(s, "{}", n).expect("Write to string will never fail");
+                } else {
+                    print!("{}", n);
+                }
+            }
+            Syscall::SetSeed => {
+                self.rngs.insert(
+                    self[A0],
+                    StdRng::seed_from_u64(
+                        //   aaaaaaaabbbbbbbb
+                        // ^     cccccccc
+                        // because why not
+                        ((self[A0] as u64) << 32 | self[A0] as u64) ^ ((self[A0] as u64) << 16),
+                    ),
+                );
+            }
+            Syscall::RandomInt => {
+                self[V0] = self.get_rng(self[A0]).r#gen();
+            }
+            Syscall::RandomIntRange => {
+                // MARS requires a positive upper bound
+                let high = self[A1] as i32;
+                if high <= 0 {
+                    return Err(Exception::syscall(SyscallError::InvalidArgument));
+                }
+                self[V0] = self.get_rng(self[A0]).gen_range(0..high as u32);
+            }
+            Syscall::RandomFloat => {
+                let f = self.get_rng(self[A0]).r#gen::<f32>();
+                self.set_f32(0, f);
+            }
+            Syscall::RandomDouble => {
+                let f = self.get_rng(self[A0]).r#gen::<f64>();
+                self.set_f64(0, f)?;
+            }
+
+            // there are no dialogs without a GUI
+            Syscall::ConfirmDialog
+            | Syscall::InputDialogInt
+            | Syscall::InputDialogFloat
+            | Syscall::InputDialogDouble
+            | Syscall::InputDialogString
+            | Syscall::MessageDialog
+            | Syscall::MessageDialogInt
+            | Syscall::MessageDialogFloat
+            | Syscall::MessageDialogDouble
+            | Syscall::MessageDialogString => {
+                return Err(Exception::syscall(SyscallError::Unimplemented))
+            }
+        }
+        Ok(InstructionResult::None)
+    }
+
+    pub fn spec_op(&mut self, inst: Inst) -> Result<InstructionResult, Exception> {
+        let Some(func) = inst.func() else {
+            return Err(Exception::new(ExcCode::RI));
+        };
+
+        // dbg!(func);
+        // eprintln!(
+        //     "[{}:{}:{}] inst.reg() = {:?}",
+        //     file!(),
+        //     line!(),
+        //     column!(),
+        //     inst.reg()
+        // ); // inlined dbg!() (ish)
+
+        let Reg {
+            rs, rt, rd, shift, ..
+        } = inst.reg();
+        match func {
+            Func::Sll => {
+                self[rd] = self[rt] << shift;
+            }
+            Func::Srl => {
+                self[rd] = self[rt] >> shift as u32;
+            }
+            Func::Sra => {
+                self[rd] = (self[rt] as i32 >> shift as i32) as u32;
+            }
+            // only the bottom 5 bits of the shift amount are used
+            Func::Sllv => {
+                self[rd] = self[rt] << (self[rs] & 0x1f);
+            }
+            Func::Srlv => {
+                self[rd] = self[rt] >> (self[rs] & 0x1f);
+            }
+            Func::Srav => {
+                self[rd] = (self[rt] as i32 >> (self[rs] & 0x1f)) as u32;
+            }
+            Func::Jr => {
+                self.jump(self[rs] as usize);
+            }
+            Func::Jalr => {
+                let target = self[rs] as usize;
+                self[rd] = self.return_addr();
+                self.jump(target);
+            }
+            Func::Movz => {
+                if self[rt] == 0 {
+                    self[rd] = self[rs];
+                }
+            }
+            Func::Movn => {
+                if self[rt] != 0 {
+                    self[rd] = self[rs];
+                }
+            }
+            Func::Syscall => {
+                // dbg!(self[V0], self[A0], self[A1]);
+                return self.syscall();
+            }
+            Func::Break => return Err(Exception::new(ExcCode::Bp)),
+            // memory accesses are already done in order
+            Func::Sync => {}
+            Func::Mfhi => self[rd] = self.hi,
+            Func::Mthi => self.hi = self[rs],
+            Func::Mflo => self[rd] = self.lo,
+            Func::Mtlo => self.lo = self[rs],
+            Func::Mult => {
+                let s = self[rs] as i32;
+                let t = self[rt] as i32;
+
+                let prod = (s as i64 * t as i64) as u64;
+
+                self.hi = (prod >> 32) as u32;
+                self.lo = (prod & 0xffff_ffff) as u32;
+            }
+            Func::MultU => {
+                let s = self[rs];
+                let t = self[rt];
+
+                let prod = s as u64 * t as u64;
+
+                self.hi = (prod >> 32) as u32;
+                self.lo = (prod & 0xffff_ffff) as u32;
+            }
+            Func::Div | Func::DivU if self[rt] == 0 => match self.div_by_zero {
+                DivPolicy::Mars => {}
+                DivPolicy::Zero => {
+                    self.hi = 0;
+                    self.lo = 0;
+                }
+                // reported as a division by zero rather than a trap when there is no handler
+                DivPolicy::Trap => return Err(Exception::new(ExcCode::Tr)),
+            },
+            Func::Div => {
+                let s = self[rs] as i32;
+                let t = self[rt] as i32;
+
+                // i32::MIN / -1 overflows, this gives i32::MIN remainder 0 like MARS whatever
+                // the `div_by_zero` policy is
+                self.hi = s.wrapping_rem(t) as u32;
+                self.lo = s.wrapping_div(t) as u32;
+            }
+            Func::DivU => {
+                let s = self[rs];
+                let t = self[rt];
+
+                self.hi = s % t;
+                self.lo = s / t;
+            }
+            Func::Add => {
+                self[rd] = overflow(i32::checked_add(self[rs] as i32, self[rt] as i32))?;
+            }
+            Func::Addu => {
+                self[rd] = self[rs].wrapping_add(self[rt]);
+            }
+            Func::Sub => {
+                self[rd] = overflow(i32::checked_sub(self[rs] as i32, self[rt] as i32))?;
+            }
+            Func::Subu => {
+                self[rd] = self[rs].wrapping_sub(self[rt]);
+            }
+            Func::And => {
+                self[rd] = self[rs] & self[rt];
+            }
+            Func::Or => {
+                self[rd] = self[rs] | self[rt];
+            }
+            Func::Xor => {
+                self[rd] = self[rs] ^ self[rt];
+            }
+            Func::Nor => {
+                self[rd] = !(self[rs] | self[rt]);
+            }
+            Func::Slt => {
+                self[rd] = u32::from((self[rs as usize] as i32) < (self[rt as usize] as i32));
+            }
+            Func::Sltu => {
+                self[rd] = u32::from(self[rs as usize] < self[rt as usize]);
+            }
+            Func::Tge | Func::Tgeu | Func::Tlt | Func::Tltu | Func::Teq | Func::Tne => {
+                if trap_condition(func as u8, self[rs], self[rt]) {
+                    return Err(Exception::new(ExcCode::Tr));
+                }
+            }
+        }
+
+        Ok(InstructionResult::None)
+    }
+
+    /// The MIPS32 SPECIAL2 group, multiply-accumulate and counting leading bits
+    pub fn spec2_op(&mut self, inst: Inst) -> Result<(), Exception> {
+        let Some(func) = inst.special2() else {
+            return Err(Exception::new(ExcCode::RI));
+        };
+        let Reg { rs, rt, rd, .. } = inst.reg();
+        let acc = (self.hi as u64) << 32 | self.lo as u64;
+        let acc = match func {
+            Special2::Mul => {
+                // unlike mult this leaves hi and lo alone
+                self[rd] = (self[rs] as i32).wrapping_mul(self[rt] as i32) as u32;
+                return Ok(());
+            }
+            Special2::Clz => {
+                self[rd] = self[rs].leading_zeros();
+                return Ok(());
+            }
+            Special2::Clo => {
+                self[rd] = self[rs].leading_ones();
+                return Ok(());
+            }
+            Special2::Madd => {
+                acc.wrapping_add((self[rs] as i32 as i64 * self[rt] as i32 as i64) as u64)
+            }
+            Special2::MaddU => acc.wrapping_add(self[rs] as u64 * self[rt] as u64),
+            Special2::Msub => {
+                acc.wrapping_sub((self[rs] as i32 as i64 * self[rt] as i32 as i64) as u64)
+            }
+            Special2::MsubU => acc.wrapping_sub(self[rs] as u64 * self[rt] as u64),
+        };
+        self.hi = (acc >> 32) as u32;
+        self.lo = (acc & 0xffff_ffff) as u32;
+        Ok(())
+    }
+
+    /// The MIPS32 Release 2 SPECIAL3 group, bit fields and byte shuffles
+    pub fn spec3_op(&mut self, inst: Inst) -> Result<(), Exception> {
+        let Reg { rs, rt, rd, .. } = inst.reg();
+        match inst.special3() {
+            Some(Special3::Ext) | Some(Special3::Ins) => {
+                let (pos, size) = inst.bit_field();
+                // the result of a field outside of the word is unpredictable, treat it as invalid
+                if size == 0 || pos as u32 + size as u32 > 32 {
+                    return Err(Exception::new(ExcCode::RI));
+                }
+                let mask = u32::MAX >> (32 - size);
+                if inst.special3() == Some(Special3::Ext) {
+                    self[rt] = (self[rs] >> pos) & mask;
+                } else {
+                    self[rt] = (self[rt] & !(mask << pos)) | (self[rs] & mask) << pos;
+                }
+            }
+            Some(Special3::Bshfl) => {
+                let t = self[rt];
+                self[rd] = match inst.bshfl() {
+                    Some(Bshfl::Wsbh) => (t & 0x00ff_00ff) << 8 | (t >> 8) & 0x00ff_00ff,
+                    Some(Bshfl::Seb) => t as i8 as u32,
+                    Some(Bshfl::Seh) => t as i16 as u32,
+                    None => return Err(Exception::new(ExcCode::RI)),
+                };
+            }
+            None => return Err(Exception::new(ExcCode::RI)),
+        }
+        Ok(())
+    }
+
+    /// Report a problem with the program without stopping it
+    fn warn(&mut self, msg: std::fmt::Arguments) {
+        if let Some(ref mut s) = self.stdout {
+            write!(s, "\n[warning] {}\n", msg).expect("Write to string will never fail");
+        } else {
+            eprintln!("[warning] {}", msg);
+        }
+    }
+
+    /// Transfer control to `target`, after the delay slot if they are enabled
+    fn jump(&mut self, target: usize) {
+        if self.delay_slots {
+            self.branch = Some(target);
+        } else {
+            self.ip = target;
+        }
+    }
+
+    /// The `offset($base)` address of a load or store
+    fn effective_addr(&self, rs: u8, imm: i16) -> usize {
+        self[rs].wrapping_add_signed(imm.into()) as usize
+    }
+
+    /// The address that linking jumps and branches return to, this skips the delay slot
+    fn return_addr(&self) -> u32 {
+        // `ip` already points after the current instruction
+        if self.delay_slots {
+            (self.ip as u32).wrapping_add(4)
+        } else {
+            self.ip as u32
+        }
+    }
+
+    /// Run one instruction, failing with a [`Fault`] when it raises an exception that there is
+    /// no handler for
+    pub fn step(&mut self) -> Result<InstructionResult, Fault> {
+        // a branch taken by the previous instruction happens after this one, its delay slot
+        let branch = self.branch.take();
+        let pc = self.ip;
+        if pc == self.memory.text.1 {
+            return Ok(InstructionResult::Done);
+        }
+        self.memory.tick(1);
+        // interrupts are taken between instructions, never in a delay slot
+        if branch.is_none() && self.interrupt_pending() {
+            return self.raise(Exception { pc, ..Exception::new(ExcCode::Int) }, None);
+        }
+        let mut dest = None;
+        let result = self.fetch().and_then(|inst| {
+            self.ip += 4;
+            dest = inst.dest();
+            self.execute(inst)
+        });
+        // $zero is hardwired, so undo anything that wrote to it.  Writes of zero are reported
+        // too, going by the instruction rather than the value.
+        let value = std::mem::take(&mut self[ZERO]);
+        if self.warn_zero && result.is_ok() && dest == Some(ZERO as u8) {
+            self.warn(format_args!("write of 0x{:08x} to $zero at 0x{:08x}", value, pc));
+        }
+        match result {
+            Ok(result) => {
+                if let Some(target) = branch {
+                    self.ip = target;
+                }
+                Ok(result)
+            }
+            Err(exc) => self.raise(Exception { pc, ..exc }, branch),
+        }
+    }
+
+    fn execute(&mut self, inst: Inst) -> Result<InstructionResult, Exception> {
+        // eprintln!();
+        // eprintln!("[{}:{}:{}] inst = {:?}", file!(), line!(), column!(), inst); // inlined dbg!() (ish)
+        match inst.kind {
+            InstKind::Special => {
+                // TODO: have exit syscall return true here
+                return self.spec_op(inst);
+            }
+            InstKind::AddI => {
+                let Imm { rs, rt, imm } = inst.imm();
+                self[rt] = overflow(i32::checked_add(self[rs] as i32, imm as i32))?;
+            }
+            InstKind::AddIU => {
+                let Imm { rs, rt, imm } = inst.imm();
+                self[rt] = self[rs].wrapping_add(imm as u32);
+            }
+            InstKind::RegImm => {
+                let Some(op) = inst.regimm() else {
+                    return Err(Exception::new(ExcCode::RI));
+                };
+                let Imm { rs, imm, .. } = inst.imm();
+                if op.is_trap() {
+                    if op.trap(self[rs], imm) {
+                        return Err(Exception::new(ExcCode::Tr));
+                    }
+                    return Ok(InstructionResult::None);
+                }
+                let taken = op.taken(self[rs] as i32);
+                if op.links() {
+                    self[RA] = self.return_addr();
+                }
+                if taken {
+                    let imm = (imm as i32) << 2;
+                    self.jump(self.ip.wrapping_add_signed(imm as isize));
+                } else if op.likely() && self.delay_slots {
+                    // branch likely only runs the delay slot when it is taken
+                    self.ip += 4;
+                }
+            }
+            InstKind::LB => {
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = self.effective_addr(rs, imm);
+                self[rt] = self.load_u8(addr)? as i8 as i32 as u32;
+            }
+            InstKind::LH => {
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = aligned(self.effective_addr(rs, imm), 2, ExcCode::AdEL)?;
+                self[rt] = self.load_u16(addr)? as i16 as i32 as u32;
+            }
+            InstKind::LW => {
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = aligned(self.effective_addr(rs, imm), 4, ExcCode::AdEL)?;
+                self[rt] = self.load_u32(addr)?;
+            }
+            InstKind::LWL => {
+                // little endian: the bytes from the aligned word up to `addr` fill the top of $t
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = self.effective_addr(rs, imm);
+                let word = self.load_u32(addr & !0b11)?;
+                let shift = 8 * (3 - (addr & 0b11));
+                let keep = ((1u64 << shift) - 1) as u32;
+                self[rt] = (word << shift) | (self[rt] & keep);
+            }
+            InstKind::LWR => {
+                // little endian: the bytes from `addr` to the end of the word fill the bottom of $t
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = self.effective_addr(rs, imm);
+                let word = self.load_u32(addr & !0b11)?;
+                let shift = 8 * (addr & 0b11);
+                self[rt] = (word >> shift) | (self[rt] & !(u32::MAX >> shift));
+            }
+            InstKind::LUI => {
+                let Imm { rt, imm, .. } = inst.imm();
+                self[rt] = (imm as u32) << 16;
+            }
+            InstKind::OrI => {
+                let Imm { rs, rt, imm } = inst.imm();
+                self[rt] = self[rs] | imm as u16 as u32;
+            }
+            InstKind::SW => {
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = aligned(self.effective_addr(rs, imm), 4, ExcCode::AdES)?;
+                self.store_u32(addr, self[rt])?;
+            }
+            InstKind::SWL => {
+                // little endian: the top bytes of $t are stored from the aligned word up to `addr`
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = self.effective_addr(rs, imm);
+                // only the bytes that change are written, so devices don't see the rest of the
+                // word being read and written back
+                let n = (addr & 0b11) + 1;
+                let bytes = (self[rt] >> (8 * (4 - n))).to_le_bytes();
+                self.store(addr & !0b11, &bytes[..n])?;
+            }
+            InstKind::SWR => {
+                // little endian: the bottom bytes of $t are stored from `addr` to the end of the
+                // word
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = self.effective_addr(rs, imm);
+                let n = 4 - (addr & 0b11);
+                self.store(addr, &self[rt].to_le_bytes()[..n])?;
+            }
+            InstKind::SB => {
+                // MEM [$s + i]:1 = LB ($t)
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = self.effective_addr(rs, imm);
+                self.store_u8(addr, self[rt] as u8)?;
+            }
+            InstKind::LL => {
+                // $rt = MEM[$base+$offset], and link the word for the following sc
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = aligned(self.effective_addr(rs, imm), 4, ExcCode::AdEL)?;
+                self[rt] = self.load_u32(addr)?;
+                self.memory.link(addr);
+            }
+            InstKind::Cop1 => return self.cop1(inst),
+            InstKind::Special2 => self.spec2_op(inst)?,
+            InstKind::Special3 => self.spec3_op(inst)?,
+            InstKind::Lwc1 | InstKind::Ldc1 | InstKind::Swc1 | InstKind::Sdc1 => {
+                self.cop1_load_store(inst)?;
+            }
+            InstKind::Bne => {
+                let Imm { rs, rt, imm } = inst.imm();
+                let imm = (imm as i32) << 2;
+                if self[rs] != self[rt] {
+                    self.jump(self.ip.wrapping_add_signed(imm as isize));
+                }
+            }
+            InstKind::Sc => {
+                // if atomic_update then memory[base+offset] ← rt, rt ← 1 else rt ← 0
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = aligned(self.effective_addr(rs, imm), 4, ExcCode::AdES)?;
+                let linked = self.memory.linked(addr);
+                if linked {
+                    self.store_u32(addr, self[rt])?;
+                }
+                self.memory.unlink();
+                self[rt] = u32::from(linked);
+            }
+            InstKind::Cache => {
+                eprintln!("CACHE OP {}", inst.opcode.rt());
+            }
+            InstKind::Beq => {
+                // if ($s == $t) pc += i << 2
+                let Imm { rs, rt, imm } = inst.imm();
+                let imm = (imm as i32) << 2;
+                if self[rs] == self[rt] {
+                    self.jump(self.ip.wrapping_add_signed(imm as isize));
+                }
+            }
+            InstKind::SltI => {
+                // $t = ($s < SE(i))
+                let Imm { rs, rt, imm } = inst.imm();
+                let imm = imm as i32;
+                self[rt] = u32::from((self[rs] as i32) < imm);
+            }
+            InstKind::J => {
+                // `ip` is already the address of the delay slot
+                self.jump(inst.jump_target(self.ip));
+            }
+            InstKind::Jal => {
+                self[RA] = self.return_addr();
+                self.jump(inst.jump_target(self.ip));
+            }
+            InstKind::Blez => {
+                let Imm { rs, imm, .. } = inst.imm();
+                let imm = (imm as i32) << 2;
+                if self[rs] as i32 <= 0 {
+                    self.jump(self.ip.wrapping_add_signed(imm as isize));
+                }
+            }
+            InstKind::Bgtz => {
+                let Imm { rs, imm, .. } = inst.imm();
+                let imm = (imm as i32) << 2;
+                if self[rs] as i32 > 0 {
+                    self.jump(self.ip.wrapping_add_signed(imm as isize));
+                }
+            }
+            InstKind::SltIU => {
+                // $t = ($s < SE(i))
+                let Imm { rs, rt, imm } = inst.imm();
+                let imm = imm as u32;
+                self[rt] = u32::from(self[rs] < imm);
+            }
+            InstKind::AndI => {
+                let Imm { rs, rt, imm } = inst.imm();
+                self[rt] = self[rs] & imm as u16 as u32;
+            }
+            InstKind::XorI => {
+                let Imm { rs, rt, imm } = inst.imm();
+                self[rt] = self[rs] ^ imm as u16 as u32;
+            }
+            InstKind::Cop0 => return self.cop0(inst),
+            InstKind::LBU => {
+                // $t = MEM[$s+i]
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = self.effective_addr(rs, imm);
+                self[rt] = self.load_u8(addr)? as u32;
+            }
+            InstKind::LHU => {
+                // $t = MEM[$s+i]
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = aligned(self.effective_addr(rs, imm), 2, ExcCode::AdEL)?;
+                self[rt] = self.load_u16(addr)? as u32;
+            }
+            InstKind::SH => {
+                // MEM [$s + i]:2 = $t
+                let Imm { rs, rt, imm } = inst.imm();
+                let addr = aligned(self.effective_addr(rs, imm), 2, ExcCode::AdES)?;
+                self.store_u16(addr, self[rt] as u16)?;
+            }
+        }
+
+        Ok(InstructionResult::None)
+    }
+
+    fn decompile(&self) -> Vec<Decomp> {
+        let mut lines = Vec::with_capacity(
+            (self.memory.text.1 - self.memory.text.0) / 4
+                + self.debug.as_ref().map(|d| d.labels.len()).unwrap_or(0),
+        );
+        let ktext = self.memory.ktext.map(|(start, end)| start..end);
+        for ip in (self.memory.text.0..self.memory.text.1)
+            .chain(ktext.into_iter().flatten())
+            .step_by(4)
+        {
+            let Some(word) = self.word_at(ip) else {
+                continue;
+            };
+            if let Some(debug) = &self.debug {
+                for (label, _) in debug.labels.iter().filter(|(_, v)| **v == ip) {
+                    lines.push(Decomp {
+                        kind: DecompKind::Label(label.to_string()),
+                        addr: ip,
+                    })
+                }
+            }
+            let kind = match Inst::new(Opcode(word)) {
+                Some(inst) => DecompKind::from(inst, ip, self.debug.as_ref()),
+                None => DecompKind::Word(word),
+            };
+            let decomp = Decomp { kind, addr: ip };
+            lines.push(decomp);
+        }
+        lines
+    }
+}
+
+/// Read a line of standard input for the `read_*` syscalls
+fn read_line() -> Result<String, Exception> {
+    let line = input::line().ok_or(Exception::syscall(SyscallError::EndOfInput))?;
+    String::from_utf8(line).map_err(|_| Exception::syscall(SyscallError::InvalidInput))
+}
+
+fn parse_input<T: std::str::FromStr>(line: &str) -> Result<T, Exception> {
+    line.trim()
+        .parse()
+        .map_err(|_| Exception::syscall(SyscallError::InvalidInput))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A greg with `code` mapped at `base`, ready to run it
+    fn greg(base: usize, code: &[Inst]) -> Greg {
+        let bytes = code
+            .iter()
+            .flat_map(|inst| inst.opcode.0.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut memory = Memory::default();
+        memory.load("text", base, &bytes, Perms::RX).unwrap();
+        memory.text = (base, base + bytes.len());
+        Greg {
+            memory,
+            ip: base,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn jump_from_the_end_of_a_region() {
+        // `j` in the last word of the region goes to the next one, where its delay slot is
+        let j = Inst::j_type(InstKind::J, 0x40);
+        let mut greg = greg(0x0fff_fffc, &[j]);
+        greg.step().unwrap();
+        assert_eq!(greg.ip, 0x1000_0100);
+
+        // with delay slots the delay slot runs first, from the next region
+        let nop = Inst::r_type(Func::Sll, 0, 0, 0, 0);
+        let mut greg = self::greg(0x0fff_fffc, &[j, nop]);
+        greg.delay_slots = true;
+        greg.step().unwrap();
+        assert_eq!((greg.ip, greg.branch), (0x1000_0000, Some(0x1000_0100)));
+        greg.step().unwrap();
+        assert_eq!(greg.ip, 0x1000_0100);
+    }
+
+    #[test]
+    fn jump_in_a_delay_slot() {
+        // `j` in the delay slot of a branch still uses its own delay slot for the region, and
+        // the branch that was pending is taken
+        let nop = Inst::r_type(Func::Sll, 0, 0, 0, 0);
+        let j = Inst::j_type(InstKind::J, 0x40);
+        let mut greg = greg(0x0fff_fff0, &[nop, nop, nop, j, nop]);
+        greg.delay_slots = true;
+        greg.ip = 0x0fff_fffc;
+        greg.branch = Some(0x0fff_fff0);
+        greg.step().unwrap();
+        assert_eq!(greg.ip, 0x0fff_fff0);
+        assert_eq!(greg.branch, Some(0x1000_0100));
+    }
+
+    #[test]
+    fn syscall_past_the_end_of_memory() {
+        // an unterminated string at the end of user data is an error at the first byte after it,
+        // not an unaligned access at its start
+        let mut greg = greg(0x0040_0000, &[Inst::r_type(Func::Syscall, 0, 0, 0, 0)]);
+        let data = 0x7fff_f000;
+        greg.memory.load("data", data, &[b'a'; 0x1000], Perms::RW).unwrap();
+        (greg[V0], greg[A0]) = (4, 0x7fff_fffc);
+        let fault = greg.step().err();
+        assert!(matches!(fault, Some(Fault::Memory { vaddr: 0x8000_0000, .. })));
+
+        // and one that runs into memory that can't be read stops there
+        greg.memory
+            .load("kernel", 0x8000_0000, &[b'a'; 4], Perms::default())
+            .unwrap();
+        greg.ip = 0x0040_0000;
+        let fault = greg.step().err();
+        assert!(matches!(fault, Some(Fault::Protection { vaddr, .. }) if vaddr == 0x8000_0000));
+    }
+
+    #[test]
+    fn warn_zero() {
+        // writes to $zero are reported even when what is written is zero
+        let nop = Inst::r_type(Func::Sll, 0, 0, 0, 0);
+        let code = [
+            nop,
+            Inst::r_type(Func::Addu, 0, 0, 0, 0),
+            Inst::i_type(InstKind::OrI, 0, 0, 5),
+            Inst::r_type(Func::Addu, 8, 0, 0, 0),
+        ];
+        let mut greg = greg(0x0040_0000, &code);
+        (greg.warn_zero, greg.stdout) = (true, Some(String::new()));
+        for _ in code {
+            greg.step().ok();
+        }
+        assert_eq!(
+            greg.stdout.as_deref(),
+            Some(concat!(
+                "\n[warning] write of 0x00000000 to $zero at 0x00400004\n",
+                "\n[warning] write of 0x00000005 to $zero at 0x00400008\n",
+            ))
+        );
+        assert_eq!(greg[ZERO], 0);
+    }
+
+    /// Run `func` on `s` and `t` with `policy`, with `hi` and `lo` starting out as 1 and 2
+    fn div(func: Func, policy: DivPolicy, s: u32, t: u32) -> Result<(u32, u32), Fault> {
+        let mut greg = greg(0x0040_0000, &[Inst::r_type(func, 0, 8, 9, 0)]);
+        greg.div_by_zero = policy;
+        (greg[8u8], greg[9u8], greg.hi, greg.lo) = (s, t, 1, 2);
+        greg.step()?;
+        Ok((greg.hi, greg.lo))
+    }
+
+    #[test]
+    fn divide_by_zero() {
+        for func in [Func::Div, Func::DivU] {
+            assert_eq!(div(func, DivPolicy::Mars, 7, 0), Ok((1, 2)));
+            assert_eq!(div(func, DivPolicy::Zero, 7, 0), Ok((0, 0)));
+            // a trap that says what it is, not the one from `teq`
+            let fault = div(func, DivPolicy::Trap, 7, 0).unwrap_err();
+            assert_eq!(fault.addr(), 0x0040_0000);
+            assert!(fault.to_string().starts_with("division by zero"));
+        }
+    }
+
+    #[test]
+    fn divide_min_by_minus_one() {
+        // the overflow gives i32::MIN remainder 0 whatever the policy is
+        let min = i32::MIN as u32;
+        for policy in [DivPolicy::Mars, DivPolicy::Zero, DivPolicy::Trap] {
+            assert_eq!(div(Func::Div, policy, min, u32::MAX), Ok((0, min)));
+            // which isn't an overflow unsigned
+            assert_eq!(div(Func::DivU, policy, min, u32::MAX), Ok((min, 0)));
+            assert_eq!(div(Func::Div, policy, 7, -2i32 as u32), Ok((1, -3i32 as u32)));
+        }
+    }
+
+    #[test]
+    fn doubles_in_odd_registers() {
+        // a reserved instruction rather than a panic, whoever asks
+        let mut greg = Greg::default();
+        let ri = Exception::new(ExcCode::RI);
+        assert_eq!(greg.get_f64(3), Err(ri));
+        assert_eq!(greg.set_f64(31, 1.0), Err(ri));
+        assert_eq!(greg.set_f64(32, 1.0), Err(ri));
+        assert_eq!(greg.set_f64(30, 1.5), Ok(()));
+        assert_eq!(greg.get_f64(30), Ok(1.5));
+    }
+
+    /// A device that always wants an interrupt
+    #[derive(Debug)]
+    struct Interrupting;
+
+    impl mmio::Device for Interrupting {
+        fn name(&self) -> &'static str {
+            "interrupting"
+        }
+
+        fn peek(&self, _offset: usize, _size: usize) -> u32 {
+            0
+        }
+
+        fn write(&mut self, _offset: usize, _size: usize, _value: u32) {}
+
+        fn interrupt(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn exceptions_without_a_handler() {
+        // each stops the program with a fault of its own
+        let nop = Inst::r_type(Func::Sll, 0, 0, 0, 0);
+        let mut greg = greg(0x0040_0000, &[nop]);
+        let device = Box::new(Interrupting);
+        greg.memory.attach(0x1000..0x1004, Some(3), device).unwrap();
+        let mut raise = |code| {
+            let exc = Exception {
+                pc: 0x0040_0000,
+                ..Exception::new(code)
+            };
+            greg.raise(exc, None).err().map(|fault| fault.to_string())
+        };
+        let at = " (0x00000000) at 0x00400000";
+        let cpu = raise(ExcCode::CpU);
+        assert_eq!(cpu, Some(format!("coprocessor unusable{}", at)));
+        let ri = raise(ExcCode::RI);
+        assert_eq!(ri, Some(format!("reserved instruction{}", at)));
+        let int = raise(ExcCode::Int);
+        assert_eq!(int, Some(format!("interrupt on line 3{}", at)));
+    }
+
+    #[test]
+    fn addresses_past_the_end() {
+        // an error rather than an overflow
+        let mut memory = Memory::default();
+        assert!(memory.map("x", usize::MAX - 1, 4, Perms::RW).is_err());
+        assert!(memory.map("x", 0xffff_fffe, 4, Perms::RW).is_err());
+        assert!(memory.load("x", usize::MAX - 1, &[0; 4], Perms::RW).is_err());
+        assert_eq!(memory.unmapped_with(usize::MAX - 1, 4, |_| true), Some(usize::MAX - 1));
+        let device = Box::new(Interrupting);
+        assert!(memory.attach(usize::MAX - 1..usize::MAX, None, device).is_err());
+        assert!(memory.map("x", 0xffff_fffc, 4, Perms::RW).is_ok());
+
+        let map = memmap::MemoryMap {
+            text: usize::MAX - 2,
+            ..memmap::MemoryMap::MARS
+        };
+        assert!(Greg::from_mars_dump(&[0; 4], None, &map).is_err());
+    }
+
+    #[test]
+    fn peeking_leaves_keys() {
+        // only the program's loads take a key from the console
+        let src = ".text\n li $t0, 0xffff0000\n lw $a0, 4($t0)\n";
+        let mut greg = Greg::from_asm(src, &memmap::MemoryMap::MARS, false).unwrap();
+        greg.memory.device::<mmio::Console>().unwrap().keys.extend(b"ab");
+        assert_eq!(greg.memory.get_u32(0xffff_0004usize), Some(b'a' as u32));
+        greg.decompile();
+        // a fault in the console's registers shows the word there
+        let exc = Exception {
+            pc: 0xffff_0004,
+            ..Exception::new(ExcCode::RI)
+        };
+        let fault = greg.raise(exc, None).err().unwrap();
+        assert_eq!(fault.word(), Some(b'a' as u32));
+        assert_eq!(greg.memory.device::<mmio::Console>().unwrap().keys.len(), 2);
+
+        let mut greg = Greg::from_asm(src, &memmap::MemoryMap::MARS, false).unwrap();
+        greg.memory.device::<mmio::Console>().unwrap().keys.extend(b"ab");
+        for _ in 0..3 {
+            greg.step().unwrap();
+        }
+        assert_eq!(greg[A0], b'a' as u32);
+        assert_eq!(greg.memory.device::<mmio::Console>().unwrap().keys, [b'b']);
+    }
+}
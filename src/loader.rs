@@ -0,0 +1,197 @@
+use anyhow::{bail, Context};
+use elf::{abi::PT_LOAD, endian::LittleEndian, ElfBytes};
+
+use crate::{
+    asm,
+    cop0::{STATUS, STATUS_RESET},
+    mem::{Memory, Perms, PAGE_SIZE},
+    memmap::{MemoryMap, DEFAULT_HEAP_LIMIT},
+    mmio::{Console, CONSOLE_SIZE, KEYBOARD_LINE},
+    reg::*,
+    DebugInfo, Greg,
+};
+
+impl Memory {
+    /// Map the parts of `map` that don't depend on the program: static data, the heap and the
+    /// stack, kernel data and MMIO
+    fn map_regions(&mut self, map: &MemoryMap) -> anyhow::Result<()> {
+        let regions = [
+            ("user data", &map.user_data),
+            ("kernel data", &map.kernel_data),
+            ("mmio", &map.mmio),
+        ];
+        for (name, region) in regions {
+            if !region.is_empty() {
+                self.map(name, region.start, region.len(), Perms::RW)?;
+            }
+        }
+        if !map.mmio.is_empty() {
+            let console = map.mmio.start..map.mmio.start + CONSOLE_SIZE;
+            self.attach(console, Some(KEYBOARD_LINE), Box::<Console>::default())?;
+        }
+        Ok(())
+    }
+}
+
+impl Greg {
+    /// Registers, exception handling and the heap set up the way MARS does for `map`
+    fn with_memory(mut memory: Memory, ip: usize, map: &MemoryMap) -> Self {
+        memory.heap = (map.heap, map.heap);
+        memory.heap_limit = DEFAULT_HEAP_LIMIT;
+        let mut greg = Greg {
+            memory,
+            ip,
+            handler: Some(map.exception_handler),
+            ..Default::default()
+        };
+        greg[GP] = map.gp;
+        greg[SP] = map.sp;
+        greg.cop0[STATUS] = STATUS_RESET;
+        greg
+    }
+}
+
+impl Greg {
+    /// Load an ELF executable by mapping each `PT_LOAD` segment at its virtual address
+    pub fn from_elf(file: &[u8], map: &MemoryMap) -> anyhow::Result<Self> {
+        let elf = ElfBytes::<LittleEndian>::minimal_parse(file).context("parsing elf")?;
+
+        let mut memory = Memory::default();
+        memory.map_regions(map)?;
+        let Some(phdrs) = elf.segments() else {
+            bail!("elf has no program headers");
+        };
+        let mut end = 0;
+        for phdr in phdrs.iter().filter(|p| p.p_type == PT_LOAD) {
+            let data = elf
+                .segment_data(&phdr)
+                .context("reading PT_LOAD segment")?;
+            if phdr.p_filesz > phdr.p_memsz {
+                bail!(
+                    "segment at 0x{:08x} has a larger file size than memory size",
+                    phdr.p_vaddr
+                );
+            }
+            // anything past `p_filesz` is left zeroed (.bss)
+            let addr = phdr.p_vaddr as usize;
+            let perms = Perms::from_elf(phdr.p_flags);
+            let name = if perms.exec { "text" } else { "data" };
+            let size = phdr.p_memsz as usize;
+            end = end.max(addr + size);
+            if !memory.mapped(addr, size) {
+                memory
+                    .map(name, addr, size, perms)
+                    .with_context(|| format!("mapping the segment at 0x{:08x}", addr))?;
+            }
+            memory.load(name, addr, data, perms)?;
+        }
+
+        let text = elf
+            .section_header_by_name(".text")?
+            .context("elf has no .text section")?;
+        memory.text = (
+            text.sh_addr as usize,
+            text.sh_addr as usize + text.sh_size as usize,
+        );
+
+        memory.ktext = elf
+            .section_header_by_name(".ktext")?
+            .map(|ktext| {
+                (
+                    ktext.sh_addr as usize,
+                    ktext.sh_addr as usize + ktext.sh_size as usize,
+                )
+            });
+
+        let data = elf
+            .section_header_by_name(".data")?
+            .or_else(|| elf.section_header_by_name(".rodata").ok().flatten());
+        memory.data = data.map(|data| {
+            (
+                data.sh_addr as usize,
+                data.sh_addr as usize + data.sh_size as usize,
+            )
+        });
+
+        let mut gp = None;
+        let mut debug = None;
+        if let Some((symtab, strtab)) = elf.symbol_table()? {
+            gp = symtab
+                .iter()
+                .find(|sym| strtab.get(sym.st_name as usize).is_ok_and(|name| name == "_gp"))
+                .map(|sym| sym.st_value as u32);
+            debug = Some(DebugInfo::from(&elf, &text));
+        }
+
+        // the heap goes after static data
+        let map = MemoryMap {
+            heap: map.heap.max(end.next_multiple_of(PAGE_SIZE)),
+            ..map.clone()
+        };
+        let mut greg = Greg::with_memory(memory, elf.ehdr.e_entry as usize, &map);
+        greg.debug = debug;
+        greg[GP] = gp.unwrap_or(greg.memory.data.map(|d| d.0).unwrap_or(0) as u32);
+
+        Ok(greg)
+    }
+}
+
+impl Greg {
+    /// Load the binary `.text` and `.data` dumps produced by MARS
+    /// (`dump .text Binary` and `dump .data Binary`) at the addresses in `map`, with registers
+    /// set up the way MARS does
+    pub fn from_mars_dump(
+        text: &[u8],
+        data: Option<&[u8]>,
+        map: &MemoryMap,
+    ) -> anyhow::Result<Self> {
+        if !text.len().is_multiple_of(4) {
+            bail!("text dump is not a whole number of instructions");
+        }
+
+        let mut memory = Memory::default();
+        memory.map_regions(map)?;
+        memory.load("text", map.text, text, Perms::RX)?;
+        memory.text = (map.text, map.text + text.len());
+
+        let data = data.unwrap_or_default();
+        memory.load("data", map.data, data, Perms::RW)?;
+        memory.data = Some((map.data, map.data + data.len()));
+
+        Ok(Greg::with_memory(memory, map.text, map))
+    }
+}
+
+impl Greg {
+    /// Assemble a MARS style program and load it at the addresses it was assembled for, to run
+    /// with or without delay slots
+    pub fn from_asm(src: &str, map: &MemoryMap, delay_slots: bool) -> anyhow::Result<Self> {
+        let asm = asm::assemble(src, map, delay_slots)?;
+        // `.text` and `.data` may have been moved by the program
+        let map = MemoryMap {
+            text: asm.text.base,
+            data: asm.data.base,
+            ..map.clone()
+        };
+        let mut greg = Greg::from_mars_dump(&asm.text.bytes, Some(&asm.data.bytes), &map)?;
+        // kernel sections are only mapped when a program has an exception handler
+        if !asm.ktext.bytes.is_empty() {
+            greg.memory
+                .load("kernel text", asm.ktext.base, &asm.ktext.bytes, Perms::RX)?;
+            greg.memory.ktext = Some((asm.ktext.base, asm.ktext.addr()));
+        }
+        greg.memory
+            .load("kernel data", asm.kdata.base, &asm.kdata.bytes, Perms::RW)?;
+        greg.ip = asm.entry();
+        greg.delay_slots = delay_slots;
+        greg.debug = Some(DebugInfo {
+            labels: asm.labels,
+            source: asm
+                .lines
+                .into_iter()
+                .map(|line| (line.addrs.start, line))
+                .collect(),
+        });
+        Ok(greg)
+    }
+}
@@ -0,0 +1,488 @@
+use std::{
+    any::Any,
+    cell::{RefCell, RefMut},
+    collections::HashMap,
+    ffi::CString,
+    fmt::Display,
+    ops::Range,
+};
+
+use anyhow::Context;
+
+use crate::mmio::Device;
+
+/// Access permissions of a mapped segment
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, PartialOrd, Ord)]
+pub struct Perms {
+    pub read: bool,
+    pub write: bool,
+    pub exec: bool,
+}
+
+impl Perms {
+    pub const RW: Self = Self {
+        read: true,
+        write: true,
+        exec: false,
+    };
+    pub const RX: Self = Self {
+        read: true,
+        write: false,
+        exec: true,
+    };
+
+    /// Build the permissions from the `p_flags` of an ELF program header
+    pub fn from_elf(flags: u32) -> Self {
+        Self {
+            read: flags & elf::abi::PF_R != 0,
+            write: flags & elf::abi::PF_W != 0,
+            exec: flags & elf::abi::PF_X != 0,
+        }
+    }
+}
+
+impl Display for Perms {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            if self.read { 'r' } else { '-' },
+            if self.write { 'w' } else { '-' },
+            if self.exec { 'x' } else { '-' },
+        )
+    }
+}
+/// Memory is allocated a page at a time, when a page is first written to
+pub const PAGE_SIZE: usize = 0x1000;
+/// Addresses are 32 bits, nothing can be mapped past this
+pub const ADDRESS_SPACE: usize = 0x1_0000_0000;
+
+/// A range of the virtual address space that may be accessed
+#[derive(Clone, Debug)]
+pub struct Segment {
+    /// What the segment holds, for reporting accesses to it
+    pub name: &'static str,
+    pub start: usize,
+    pub end: usize,
+    pub perms: Perms,
+}
+
+impl Segment {
+    fn contains(&self, addr: usize) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+}
+
+/// The address space, a set of mapped segments backed by a sparse table of pages.  Pages that
+/// have not been written to read as zero.  Accesses to an attached device go to the device
+/// instead.
+#[derive(Debug, Default)]
+pub struct Memory {
+    // (start, end)
+    pub data: Option<(usize, usize)>,
+    pub text: (usize, usize),
+    // kernel text, where exception handlers live
+    pub ktext: Option<(usize, usize)>,
+    // (start, break), the break is the end of the heap and moves with `alloc`
+    pub heap: (usize, usize),
+    // How large the heap may grow
+    pub heap_limit: usize,
+
+    segments: Vec<Segment>,
+    // page number: page
+    pages: HashMap<usize, Box<[u8; PAGE_SIZE]>>,
+    // The word linked by `ll`, this is the LLbit along with the address that it was set for
+    link: Option<usize>,
+    devices: Vec<Attached>,
+}
+
+/// A device and where it is
+#[derive(Debug)]
+struct Attached {
+    range: Range<usize>,
+    // Its interrupt line, it sets Cause bit 8 + line
+    line: Option<u8>,
+    // Behind a cell since reading its registers may change it
+    device: RefCell<Box<dyn Device>>,
+}
+
+impl Memory {
+    /// Map `size` bytes at `start`, they read as zero until they are written
+    pub fn map(
+        &mut self,
+        name: &'static str,
+        start: usize,
+        size: usize,
+        perms: Perms,
+    ) -> anyhow::Result<()> {
+        let end = match start.checked_add(size) {
+            Some(end) if end <= ADDRESS_SPACE => end,
+            _ => anyhow::bail!(
+                "{} of 0x{:x} bytes at 0x{:08x} doesn't fit in the address space",
+                name,
+                size,
+                start
+            ),
+        };
+        if let Some(s) = self.segments.iter().find(|s| start < s.end && s.start < end) {
+            anyhow::bail!(
+                "{} 0x{:08x}..0x{:08x} overlaps {} 0x{:08x}..0x{:08x}",
+                name,
+                start,
+                end,
+                s.name,
+                s.start,
+                s.end
+            );
+        }
+        self.segments.push(Segment {
+            name,
+            start,
+            end,
+            perms,
+        });
+        Ok(())
+    }
+
+    /// Write `bytes` to `addr`, mapping them as `name` with `perms` unless they are already
+    /// mapped
+    pub fn load(
+        &mut self,
+        name: &'static str,
+        addr: usize,
+        bytes: &[u8],
+        perms: Perms,
+    ) -> anyhow::Result<()> {
+        if !self.mapped(addr, bytes.len()) {
+            self.map(name, addr, bytes.len(), perms)?;
+        }
+        self.write(addr, bytes)
+            .context("loading into memory that is partially mapped")
+    }
+
+    /// Attach `device` at `range`, which is mapped for it unless it is already.  It
+    /// interrupts on `line` (0 to 7) if it has one.
+    pub fn attach(
+        &mut self,
+        range: Range<usize>,
+        line: Option<u8>,
+        device: Box<dyn Device>,
+    ) -> anyhow::Result<()> {
+        let name = device.name();
+        if let Some(other) = self
+            .devices
+            .iter()
+            .find(|a| range.start < a.range.end && a.range.start < range.end)
+        {
+            anyhow::bail!(
+                "{} 0x{:08x}..0x{:08x} overlaps {} 0x{:08x}..0x{:08x}",
+                name,
+                range.start,
+                range.end,
+                other.device.borrow().name(),
+                other.range.start,
+                other.range.end
+            );
+        }
+        if line.is_some_and(|line| line >= 8) {
+            anyhow::bail!("{} can't interrupt on line {}", name, line.unwrap());
+        }
+        if !self.mapped(range.start, range.len()) {
+            self.map(name, range.start, range.len(), Perms::RW)?;
+        }
+        self.devices.push(Attached {
+            range,
+            line,
+            device: RefCell::new(device),
+        });
+        Ok(())
+    }
+
+    /// The devices and where they are
+    pub fn devices(&self) -> impl Iterator<Item = (Range<usize>, RefMut<'_, dyn Device>)> {
+        self.devices
+            .iter()
+            .map(|a| (a.range.clone(), RefMut::map(a.device.borrow_mut(), |d| &mut **d)))
+    }
+
+    /// The first device of type `T`
+    pub fn device<T: Device>(&self) -> Option<RefMut<'_, T>> {
+        self.devices.iter().find_map(|a| {
+            RefMut::filter_map(a.device.borrow_mut(), |d| {
+                (&mut **d as &mut dyn Any).downcast_mut::<T>()
+            })
+            .ok()
+        })
+    }
+
+    /// Let `cycles` instructions worth of time pass for the devices
+    pub fn tick(&self, cycles: u64) {
+        for a in &self.devices {
+            a.device.borrow_mut().tick(cycles);
+        }
+    }
+
+    /// The interrupt lines of devices that are asking for an interrupt, as Cause bits
+    pub fn interrupts(&self) -> u32 {
+        self.devices
+            .iter()
+            .filter(|a| a.device.borrow().interrupt())
+            .filter_map(|a| a.line)
+            .fold(0, |bits, line| bits | 1 << (8 + line))
+    }
+
+    /// Split the part of `addr..addr + len` that is in `range` into aligned accesses of at most a
+    /// word, as (offset in the device, offset in the access, size)
+    fn device_accesses(
+        range: &Range<usize>,
+        addr: usize,
+        len: usize,
+    ) -> impl Iterator<Item = (usize, usize, usize)> {
+        let (start, end) = (range.start, addr.saturating_add(len).min(range.end));
+        let mut at = addr.max(start);
+        std::iter::from_fn(move || {
+            let size = [4, 2, 1].into_iter().find(|&size| {
+                (at - start).is_multiple_of(size) && size <= end.saturating_sub(at)
+            })?;
+            at += size;
+            Some((at - size - start, at - size - addr, size))
+        })
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// The segment that `addr` is in, `None` if it is not mapped
+    pub fn segment(&self, addr: usize) -> Option<&Segment> {
+        self.segments.iter().find(|s| s.contains(addr))
+    }
+
+    /// The permissions of the segment that `addr` is in, `None` if it is not mapped
+    pub fn perms(&self, addr: usize) -> Option<Perms> {
+        self.segment(addr).map(|s| s.perms)
+    }
+
+    /// Whether all of `addr..addr + len` is mapped, it may span adjacent segments
+    pub fn mapped(&self, addr: usize, len: usize) -> bool {
+        self.unmapped_with(addr, len, |_| true).is_none()
+    }
+
+    /// The first byte of `addr..addr + len` that isn't mapped by a segment with permissions that
+    /// are `allowed`, `None` if there isn't one
+    pub fn unmapped_with(
+        &self,
+        mut addr: usize,
+        len: usize,
+        allowed: impl Fn(Perms) -> bool,
+    ) -> Option<usize> {
+        // bytes past the end of the address space aren't mapped either
+        let end = addr.saturating_add(len);
+        while addr < end {
+            match self.segment(addr) {
+                Some(segment) if allowed(segment.perms) => addr = segment.end,
+                _ => return Some(addr),
+            }
+        }
+        None
+    }
+
+    /// Split `addr..addr + len` at page boundaries, into (page number, offset in page, offset
+    /// in the access, length)
+    fn chunks(addr: usize, len: usize) -> impl Iterator<Item = (usize, usize, usize, usize)> {
+        let mut done = 0;
+        std::iter::from_fn(move || {
+            if done == len {
+                return None;
+            }
+            let at = addr + done;
+            let offset = at % PAGE_SIZE;
+            let n = (PAGE_SIZE - offset).min(len - done);
+            done += n;
+            Some((at / PAGE_SIZE, offset, done - n, n))
+        })
+    }
+
+    /// Fill `buf` from `addr`, `None` if any of it is not mapped.  This is a load by the program,
+    /// devices see it.
+    pub fn read(&self, addr: usize, buf: &mut [u8]) -> Option<()> {
+        self.fill(addr, buf, |a, offset, size| {
+            a.device.borrow_mut().read(offset, size)
+        })
+    }
+
+    /// Fill `buf` from `addr` like `read`, but without devices seeing it.  For looking at
+    /// memory from outside the program, like the TUI and fault reports do.
+    pub fn peek(&self, addr: usize, buf: &mut [u8]) -> Option<()> {
+        self.fill(addr, buf, |a, offset, size| {
+            a.device.borrow().peek(offset, size)
+        })
+    }
+
+    fn fill(
+        &self,
+        addr: usize,
+        buf: &mut [u8],
+        device_read: impl Fn(&Attached, usize, usize) -> u32,
+    ) -> Option<()> {
+        if !self.mapped(addr, buf.len()) {
+            return None;
+        }
+        for (page, offset, at, n) in Self::chunks(addr, buf.len()) {
+            let buf = &mut buf[at..][..n];
+            match self.pages.get(&page) {
+                Some(page) => buf.copy_from_slice(&page[offset..][..n]),
+                None => buf.fill(0),
+            }
+        }
+        for a in &self.devices {
+            for (offset, at, size) in Self::device_accesses(&a.range, addr, buf.len()) {
+                let value = device_read(a, offset, size);
+                buf[at..][..size].copy_from_slice(&value.to_le_bytes()[..size]);
+            }
+        }
+        Some(())
+    }
+
+    /// Write `bytes` to `addr`, `None` if any of it is not mapped
+    pub fn write(&mut self, addr: usize, bytes: &[u8]) -> Option<()> {
+        if !self.mapped(addr, bytes.len()) {
+            return None;
+        }
+        // every store goes through here, so this is where a store breaks the link
+        if self
+            .link
+            .is_some_and(|link| addr < link + 4 && link < addr + bytes.len())
+        {
+            self.link = None;
+        }
+        for (page, offset, at, n) in Self::chunks(addr, bytes.len()) {
+            let page = self
+                .pages
+                .entry(page)
+                .or_insert_with(|| Box::new([0; PAGE_SIZE]));
+            page[offset..][..n].copy_from_slice(&bytes[at..][..n]);
+        }
+        for a in &self.devices {
+            for (offset, at, size) in Self::device_accesses(&a.range, addr, bytes.len()) {
+                let mut value = [0; 4];
+                value[..size].copy_from_slice(&bytes[at..][..size]);
+                a.device
+                    .borrow_mut()
+                    .write(offset, size, u32::from_le_bytes(value));
+            }
+        }
+        Some(())
+    }
+
+    /// Read `len` bytes from `addr`
+    pub fn bytes(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        let mut buf = vec![0; len];
+        self.read(addr, &mut buf)?;
+        Some(buf)
+    }
+
+    /// Set the LLbit for the word at `addr`, the following `sc` to it succeeds unless the word is
+    /// stored to in between
+    pub fn link(&mut self, addr: usize) {
+        self.link = Some(addr);
+    }
+
+    /// Whether the word at `addr` is still linked
+    pub fn linked(&self, addr: usize) -> bool {
+        self.link == Some(addr)
+    }
+
+    /// Clear the LLbit, done by `eret` and needed whenever another thread of execution could
+    /// have run since the `ll`
+    pub fn unlink(&mut self) {
+        self.link = None;
+    }
+
+    /// Read the nul-terminated string starting at `addr`, `None` if it runs out of mapped memory
+    pub fn cstr(&self, addr: usize) -> Option<CString> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.get_u8(addr + bytes.len())? {
+                0 => return CString::new(bytes).ok(),
+                b => bytes.push(b),
+            }
+        }
+    }
+
+    /// Grow the heap by `count` bytes, returning the old break, or `None` if it would grow past
+    /// the limit or into something else.  The new memory reads as zero.
+    pub fn alloc(&mut self, count: usize) -> Option<usize> {
+        let (start, brk) = self.heap;
+        let end = brk.checked_add(count)?;
+        if end - start > self.heap_limit {
+            return None;
+        }
+        // the MARS layouts have the heap in the user data region already
+        if !self.mapped(brk, count) {
+            let end = end.next_multiple_of(PAGE_SIZE);
+            match self.segments.iter().position(|s| s.name == "heap") {
+                // grow the heap segment, as long as it doesn't run into another one
+                Some(i) => {
+                    let heap_end = self.segments[i].end;
+                    if self.segments.iter().any(|s| heap_end < s.end && s.start < end) {
+                        return None;
+                    }
+                    self.segments[i].end = end;
+                }
+                None => self.map("heap", start, end - start, Perms::RW).ok()?,
+            }
+        }
+        self.heap.1 += count;
+        Some(brk)
+    }
+
+    // the getters peek, the program's loads go through `read`
+    fn get<const N: usize>(&self, addr: usize) -> Option<[u8; N]> {
+        let mut buf = [0; N];
+        self.peek(addr, &mut buf)?;
+        Some(buf)
+    }
+
+    pub fn get_u8<I>(&self, index: I) -> Option<u8>
+    where
+        I: Into<usize>,
+    {
+        self.get(index.into()).map(u8::from_le_bytes)
+    }
+
+    pub fn set_u8<I>(&mut self, index: I, value: u8) -> Option<()>
+    where
+        I: Into<usize>,
+    {
+        self.write(index.into(), &value.to_le_bytes())
+    }
+
+    pub fn get_u16<I>(&self, index: I) -> Option<u16>
+    where
+        I: Into<usize>,
+    {
+        self.get(index.into()).map(u16::from_le_bytes)
+    }
+
+    pub fn set_u16<I>(&mut self, index: I, value: u16) -> Option<()>
+    where
+        I: Into<usize>,
+    {
+        self.write(index.into(), &value.to_le_bytes())
+    }
+
+    pub fn get_u32<I>(&self, index: I) -> Option<u32>
+    where
+        I: Into<usize>,
+    {
+        self.get(index.into()).map(u32::from_le_bytes)
+    }
+
+    pub fn set_u32<I>(&mut self, index: I, value: u32) -> Option<()>
+    where
+        I: Into<usize>,
+    {
+        self.write(index.into(), &value.to_le_bytes())
+    }
+}
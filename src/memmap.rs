@@ -0,0 +1,121 @@
+//! The layout of the address space
+//!
+//! | Region              | MARS default              | Compact, text at 0  |
+//! |---------------------|---------------------------|---------------------|
+//! | `.text`             | 0x00400000                | 0x00000000          |
+//! | static data, heap   | 0x10000000                | 0x00001000          |
+//! | `.data`             | 0x10010000                | 0x00002000          |
+//! | `$gp`               | 0x10008000                | 0x00001800          |
+//! | heap                | 0x10040000                | 0x00003000          |
+//! | `$sp`               | 0x7fffeffc                | 0x00003ffc          |
+//! | `.ktext`            | 0x80000000                | 0x00004000          |
+//! | exception handler   | 0x80000180                | 0x00004180          |
+//! | `.kdata`            | 0x90000000..0xffff0000    | 0x00005000..0x7f00  |
+//! | MMIO                | 0xffff0000..=0xffffffff   | 0x00007f00..=0x7fff |
+//!
+//! These are the memory configurations of MARS.  Static data, the heap and the stack share one
+//! readable and writable region (0x10000000..0x80000000 by default), which is only backed by
+//! memory where it is written.  Code is mapped read and execute only, for as long as the program
+//! is.
+//!
+//! The ELF-defined map uses the `PT_LOAD` segments of an executable instead, with a 1 MiB stack
+//! below the MARS `$sp` and the MARS MMIO region.  Its heap starts on the page after the
+//! executable.
+//!
+//! The heap grows with `sbrk` up to a limit, [`DEFAULT_HEAP_LIMIT`] unless it is changed.
+
+use std::ops::Range;
+
+use clap::ValueEnum;
+
+/// Size of the stack when the executable decides the rest of the memory map
+pub const ELF_STACK_SIZE: usize = 1024 * 1024;
+
+/// How far the heap may grow
+pub const DEFAULT_HEAP_LIMIT: usize = 256 * 1024 * 1024;
+
+#[derive(ValueEnum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Layout {
+    /// The MARS default, text at 0x00400000 and data at 0x10010000
+    Mars,
+    /// MARS "compact, text at address 0", everything fits below 0x8000
+    CompactText,
+    /// The segments of an ELF executable
+    Elf,
+}
+
+/// Where each part of a program goes
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MemoryMap {
+    /// Where `.text` is assembled and text dumps are loaded
+    pub text: usize,
+    /// Where `.data` is assembled and data dumps are loaded
+    pub data: usize,
+    pub ktext: usize,
+    pub kdata: usize,
+    pub exception_handler: usize,
+    /// Initial `$gp`
+    pub gp: u32,
+    /// Initial `$sp`
+    pub sp: u32,
+    /// Start of the heap, for ELF executables it is moved past the end of the executable
+    pub heap: usize,
+    /// Static data, the heap and the stack, readable and writable
+    pub user_data: Range<usize>,
+    /// Kernel data, readable and writable
+    pub kernel_data: Range<usize>,
+    /// Memory mapped devices
+    pub mmio: Range<usize>,
+}
+
+impl MemoryMap {
+    pub const MARS: Self = Self {
+        text: 0x0040_0000,
+        data: 0x1001_0000,
+        ktext: 0x8000_0000,
+        kdata: 0x9000_0000,
+        exception_handler: 0x8000_0180,
+        // the middle of the 64 KiB `.extern` block at 0x10000000
+        gp: 0x1000_8000,
+        sp: 0x7fff_effc,
+        heap: 0x1004_0000,
+        user_data: 0x1000_0000..0x8000_0000,
+        kernel_data: 0x9000_0000..0xffff_0000,
+        mmio: 0xffff_0000..0x1_0000_0000,
+    };
+
+    pub const COMPACT_TEXT: Self = Self {
+        text: 0x0000,
+        data: 0x2000,
+        ktext: 0x4000,
+        kdata: 0x5000,
+        exception_handler: 0x4180,
+        gp: 0x1800,
+        sp: 0x3ffc,
+        heap: 0x3000,
+        user_data: 0x1000..0x4000,
+        kernel_data: 0x5000..0x7f00,
+        mmio: 0x7f00..0x8000,
+    };
+
+    /// Only the stack and MMIO, everything else comes from the executable
+    pub const ELF: Self = {
+        let stack_end = (Self::MARS.sp as usize + 4).next_multiple_of(0x1000);
+        Self {
+            heap: 0,
+            user_data: stack_end - ELF_STACK_SIZE..stack_end,
+            kernel_data: 0..0,
+            ..Self::MARS
+        }
+    };
+}
+
+impl Layout {
+    pub fn map(self) -> MemoryMap {
+        match self {
+            Layout::Mars => MemoryMap::MARS,
+            Layout::CompactText => MemoryMap::COMPACT_TEXT,
+            Layout::Elf => MemoryMap::ELF,
+        }
+    }
+}
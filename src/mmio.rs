@@ -0,0 +1,369 @@
+//! Memory mapped devices
+//!
+//! A [`Device`] is attached to [`Memory`] at a range of addresses, and loads and stores there go
+//! to it instead of memory.  Each device may have an interrupt line, line `n` is Cause and Status
+//! bit `8 + n`, and a panel in the TUI.
+//!
+//! The keyboard and display is the one from MARS ("Keyboard and Display MMIO Simulator"), four
+//! word registers at the start of the MMIO region:
+//!
+//! | Offset | Register             | Bits                                                      |
+//! |--------|----------------------|-----------------------------------------------------------|
+//! | 0x0    | receiver control     | 0: a key is ready, 1: interrupt when a key is ready (rw)   |
+//! | 0x4    | receiver data        | the key, reading it clears the ready bit                  |
+//! | 0x8    | transmitter control  | 0: the display is ready, 1: interrupt enable (rw)         |
+//! | 0xc    | transmitter data     | writing a byte here shows it on the display               |
+//!
+//! A key interrupt is on line 0 (Cause bit 8), taken while it is enabled in Status.
+//!
+//! The bitmap display is MARS's too, a framebuffer of `0x00RRGGBB` words, one for each unit of
+//! the display, row by row.
+//!
+//! [`Memory`]: crate::mem::Memory
+
+use std::{
+    any::Any,
+    collections::VecDeque,
+    fmt::Debug,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use ratatui::{layout::Rect, style::Color, Frame};
+
+use crate::{
+    input,
+    mem::{Memory, ADDRESS_SPACE},
+    tui::draw_tail,
+};
+
+/// Something that is accessed through memory
+pub trait Device: Any + Debug {
+    /// What it is, for the TUI and for errors about it
+    fn name(&self) -> &'static str;
+
+    /// The `size` (1, 2 or 4) bytes at `offset`, which is aligned to `size`, without any of the
+    /// effects of a load.  The value is in the low bytes, anything above them is ignored.
+    fn peek(&self, offset: usize, size: usize) -> u32;
+
+    /// Load `size` bytes at `offset` like `peek`, for devices that a load does something to
+    fn read(&mut self, offset: usize, size: usize) -> u32 {
+        self.peek(offset, size)
+    }
+
+    /// Store the low `size` (1, 2 or 4) bytes of `value` at `offset`, which is aligned to `size`
+    fn write(&mut self, offset: usize, size: usize, value: u32);
+
+    /// Let `cycles` instructions worth of time pass
+    fn tick(&mut self, _cycles: u64) {}
+
+    /// Whether it is asking for an interrupt, on the line it was attached with
+    fn interrupt(&self) -> bool {
+        false
+    }
+
+    /// Title of its panel in the TUI, `None` while it has nothing to show
+    fn panel(&self) -> Option<String> {
+        None
+    }
+
+    /// Draw its panel into `rect`
+    fn draw(&self, _frame: &mut Frame, _rect: Rect) {}
+
+    /// Whether keys typed into the TUI go to it
+    fn takes_keys(&self) -> bool {
+        false
+    }
+
+    /// A key typed into the TUI, as the byte it types
+    fn key(&mut self, _byte: u8) {}
+}
+
+pub const RECEIVER_CONTROL: usize = 0x0;
+pub const RECEIVER_DATA: usize = 0x4;
+pub const TRANSMITTER_CONTROL: usize = 0x8;
+pub const TRANSMITTER_DATA: usize = 0xc;
+/// Bytes taken up by the registers
+pub const CONSOLE_SIZE: usize = 0x10;
+/// Interrupt line of the keyboard
+pub const KEYBOARD_LINE: u8 = 0;
+
+const READY: u32 = 1 << 0;
+const INTERRUPT_ENABLE: u32 = 1 << 1;
+
+/// The keyboard and display
+#[derive(Debug, Default)]
+pub struct Console {
+    /// Keys that have been typed into the TUI but not read, the first one is in the receiver
+    /// data register
+    pub keys: VecDeque<u8>,
+    /// Everything written to the display
+    pub display: String,
+    /// Take keys from stdin and write the display to stdout, instead of keeping them for the TUI
+    pub stdio: bool,
+    /// Whether the program has touched the registers
+    pub used: bool,
+    // Whether the program has asked for keys, by reading the receiver or enabling its
+    // interrupt.  Until then stdin is left alone.
+    receiving: bool,
+    // The receiver data register keeps the last key after it has been read
+    last_key: u8,
+    receiver_ie: bool,
+    transmitter_ie: bool,
+}
+
+impl Console {
+    fn register(&self, offset: usize) -> u32 {
+        let control = |ready: bool, ie: bool| {
+            (if ready { READY } else { 0 }) | (if ie { INTERRUPT_ENABLE } else { 0 })
+        };
+        match offset {
+            RECEIVER_CONTROL => control(self.key().is_some(), self.receiver_ie),
+            RECEIVER_DATA => self.key().unwrap_or(self.last_key) as u32,
+            // the display is always ready, characters show up as soon as they are written
+            TRANSMITTER_CONTROL => control(true, self.transmitter_ie),
+            _ => 0,
+        }
+    }
+
+    fn show(&mut self, byte: u8) {
+        if self.stdio {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(&[byte]);
+            let _ = stdout.flush();
+        } else {
+            self.display.push(byte as char);
+        }
+    }
+
+    /// The key that is ready, stdin is shared with the `read_*` syscalls
+    fn key(&self) -> Option<u8> {
+        match (self.stdio, self.receiving) {
+            (true, true) => input::peek(),
+            (true, false) => None,
+            (false, _) => self.keys.front().copied(),
+        }
+    }
+
+    fn take_key(&mut self) {
+        let key = match self.stdio {
+            true => input::peek().and_then(|_| input::next()),
+            false => self.keys.pop_front(),
+        };
+        if let Some(key) = key {
+            self.last_key = key;
+        }
+    }
+}
+
+impl Device for Console {
+    fn name(&self) -> &'static str {
+        "keyboard and display"
+    }
+
+    fn peek(&self, offset: usize, _size: usize) -> u32 {
+        self.register(offset & !3) >> (offset % 4 * 8)
+    }
+
+    fn read(&mut self, offset: usize, size: usize) -> u32 {
+        self.used = true;
+        let register = offset & !3;
+        if register == RECEIVER_CONTROL || register == RECEIVER_DATA {
+            self.receiving = true;
+        }
+        let value = self.peek(offset, size);
+        // the key is in the low byte, reading only the bytes above it leaves it
+        if offset == RECEIVER_DATA {
+            self.take_key();
+        }
+        value
+    }
+
+    fn write(&mut self, offset: usize, _size: usize, value: u32) {
+        self.used = true;
+        // only the lowest byte of each register is writable
+        match offset {
+            RECEIVER_CONTROL => {
+                self.receiver_ie = value & INTERRUPT_ENABLE != 0;
+                // waiting for the interrupt is asking for keys too
+                self.receiving |= self.receiver_ie;
+            }
+            TRANSMITTER_CONTROL => self.transmitter_ie = value & INTERRUPT_ENABLE != 0,
+            TRANSMITTER_DATA => self.show(value as u8),
+            _ => {}
+        }
+    }
+
+    fn interrupt(&self) -> bool {
+        self.receiver_ie && self.key().is_some()
+    }
+
+    fn panel(&self) -> Option<String> {
+        // it only gets a panel once it is used
+        (self.used || !self.keys.is_empty()).then(|| match self.keys.len() {
+            0 => "Display".into(),
+            1 => "Display (1 key waiting)".into(),
+            n => format!("Display ({} keys waiting)", n),
+        })
+    }
+
+    fn draw(&self, frame: &mut Frame, rect: Rect) {
+        draw_tail(&self.display, frame, rect);
+    }
+
+    fn takes_keys(&self) -> bool {
+        true
+    }
+
+    fn key(&mut self, byte: u8) {
+        self.keys.push_back(byte);
+    }
+}
+
+/// The bitmap display
+#[derive(Debug)]
+pub struct Bitmap {
+    /// Address of the top left unit
+    pub base: usize,
+    /// Size in units
+    pub width: usize,
+    pub height: usize,
+    /// Size of a unit in pixels, for snapshots
+    pub unit: (usize, usize),
+    // The framebuffer
+    pixels: Vec<u8>,
+}
+
+impl Bitmap {
+    /// A display of `size` pixels made of `unit` sized units with its framebuffer at `base`, the
+    /// framebuffer starts out as what is in `memory` there, or black if it isn't mapped
+    pub fn new(
+        memory: &Memory,
+        base: usize,
+        size: (usize, usize),
+        unit: (usize, usize),
+    ) -> anyhow::Result<Self> {
+        if size.0 < unit.0 || size.1 < unit.1 || unit.0 == 0 || unit.1 == 0 {
+            anyhow::bail!(
+                "a {}x{} bitmap display can't be made of {}x{} units",
+                size.0,
+                size.1,
+                unit.0,
+                unit.1
+            );
+        }
+        let (width, height) = (size.0 / unit.0, size.1 / unit.1);
+        let Some(len) = width
+            .checked_mul(height)
+            .and_then(|units| units.checked_mul(4))
+            .filter(|&len| {
+                base.checked_add(len)
+                    .is_some_and(|end| end <= ADDRESS_SPACE)
+            })
+        else {
+            anyhow::bail!(
+                "a {}x{} bitmap display at 0x{:08x} doesn't fit in the address space",
+                width,
+                height,
+                base
+            );
+        };
+        let mut pixels = vec![0; len];
+        // what is already there, it stays zero if it isn't mapped yet
+        memory.peek(base, &mut pixels);
+        Ok(Self {
+            base,
+            width,
+            height,
+            unit,
+            pixels,
+        })
+    }
+
+    /// Bytes taken up by the framebuffer
+    pub fn size(&self) -> usize {
+        self.pixels.len()
+    }
+
+    /// The colour of the unit at `x`, `y` as (red, green, blue)
+    pub fn rgb(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let [b, g, r, _] = self.pixels[(y * self.width + x) * 4..][..4] else {
+            unreachable!()
+        };
+        (r, g, b)
+    }
+
+    /// The display as a binary PPM image, each unit is drawn at its size in pixels
+    pub fn ppm(&self) -> Vec<u8> {
+        let (width, height) = (self.width * self.unit.0, self.height * self.unit.1);
+        let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+        for y in 0..height {
+            for x in 0..width {
+                let (r, g, b) = self.rgb(x / self.unit.0, y / self.unit.1);
+                ppm.extend([r, g, b]);
+            }
+        }
+        ppm
+    }
+
+    /// Save the display to `path` as a PPM image
+    pub fn snapshot(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.ppm())
+    }
+}
+
+impl Device for Bitmap {
+    fn name(&self) -> &'static str {
+        "bitmap display"
+    }
+
+    fn peek(&self, offset: usize, size: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes[..size].copy_from_slice(&self.pixels[offset..][..size]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn write(&mut self, offset: usize, size: usize, value: u32) {
+        self.pixels[offset..][..size].copy_from_slice(&value.to_le_bytes()[..size]);
+    }
+
+    fn panel(&self) -> Option<String> {
+        Some(format!(
+            "Bitmap {}x{} at 0x{:08x}",
+            self.width, self.height, self.base
+        ))
+    }
+
+    /// Scaled down to fit, with two units to a cell as half blocks
+    fn draw(&self, frame: &mut Frame, rect: Rect) {
+        let (width, height) = (rect.width as usize, rect.height as usize * 2);
+        if width == 0 || height == 0 {
+            return;
+        }
+        // units to a cell, the same both ways to keep the shape of the display
+        let scale = self
+            .width
+            .div_ceil(width)
+            .max(self.height.div_ceil(height))
+            .max(1);
+        let colour = |x: usize, y: usize| {
+            if y < self.height {
+                let (r, g, b) = self.rgb(x, y);
+                Color::Rgb(r, g, b)
+            } else {
+                Color::Reset
+            }
+        };
+        let buf = frame.buffer_mut();
+        for y in 0..self.height.div_ceil(scale * 2) {
+            for x in 0..self.width.div_ceil(scale) {
+                let (ux, uy) = (x * scale, y * 2 * scale);
+                buf[(rect.x + x as u16, rect.y + y as u16)]
+                    .set_char('▀')
+                    .set_fg(colour(ux, uy))
+                    .set_bg(colour(ux, uy + scale));
+            }
+        }
+    }
+}
@@ -211,3 +211,31 @@ impl From<&Reg> for Span<'static> {
         val.into_span()
     }
 }
+
+/// A coprocessor 1 (floating point) register, doubles use an even/odd pair
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub struct FReg(pub u8);
+
+impl FReg {
+    pub fn into_span(self) -> Span<'static> {
+        self.to_string().fg(Color::LightYellow)
+    }
+}
+
+impl From<u8> for FReg {
+    fn from(value: u8) -> Self {
+        Self(value)
+    }
+}
+
+impl Display for FReg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "$f{}", self.0)
+    }
+}
+
+impl From<&FReg> for Span<'static> {
+    fn from(val: &FReg) -> Self {
+        val.into_span()
+    }
+}
@@ -8,14 +8,16 @@ use ratatui::{
     crossterm::event::{self, Event, KeyCode, KeyEventKind},
     layout::{Alignment, Constraint, Layout, Rect},
     style::{Color, Modifier, Style, Stylize},
-    text::{Line, Text},
+    text::{Line, Span, Text},
     widgets::{Block, Borders},
     DefaultTerminal, Frame,
 };
 
 use crate::{
+    cop0::Fault,
     decomp::{Addr, Decomp, DecompKind},
-    reg::Reg,
+    fpu::java_float,
+    reg::{FReg, Reg, ZERO},
     Greg, InstructionResult,
 };
 
@@ -76,10 +78,18 @@ struct State {
     curr_reg: usize,
     curr_buf: u32,
     prev_regs: [u32; 32],
+    prev_fregs: [u32; 32],
+    // Show the floating point registers instead of the general purpose ones
+    show_fpu: bool,
     greg: Greg,
     decomp: Vec<Decomp>,
     halt: bool,
+    // Why the program stopped, shown in the status bar
+    status: Option<Span<'static>>,
     display_mode: DisplayMode,
+    // Keys go to the devices that take them instead of controlling the TUI
+    typing: bool,
+    show_panels: bool,
 }
 
 impl State {
@@ -89,30 +99,82 @@ impl State {
             curr_reg: 0,
             curr_buf: 0,
             prev_regs: Default::default(),
+            prev_fregs: Default::default(),
+            show_fpu: false,
             decomp: greg.decompile(),
             greg,
             halt: false,
+            status: None,
             display_mode: DisplayMode::Hex,
+            typing: false,
+            show_panels: true,
+        }
+    }
+
+    /// Pass a key to the devices that take keys, as the byte it types
+    fn type_key(&mut self, code: KeyCode) {
+        let byte = match code {
+            KeyCode::Char(c) if c.is_ascii() => c as u8,
+            KeyCode::Enter => b'\n',
+            KeyCode::Tab => b'\t',
+            KeyCode::Backspace => 0x08,
+            _ => return,
+        };
+        for (_, mut device) in self.greg.memory.devices() {
+            if device.takes_keys() {
+                device.key(byte);
+            }
         }
     }
 
     fn step(&mut self) {
         if !self.halt {
             self.prev_regs.copy_from_slice(&self.greg.reg);
+            self.prev_fregs.copy_from_slice(&self.greg.freg);
+            self.status = None;
             match self.greg.step() {
-                InstructionResult::None => {}
-                InstructionResult::Done => {
+                Ok(InstructionResult::None) => {}
+                Ok(InstructionResult::Done) => {
                     self.halt = true;
                     PLAY.store(false, Ordering::Relaxed);
+                    self.status = Some("finished".green());
                 }
-                InstructionResult::Exit(_) => {
+                Ok(InstructionResult::Exit(code)) => {
                     self.halt = true;
                     PLAY.store(false, Ordering::Relaxed);
+                    self.status = Some(format!("exited with code {}", code).green());
+                }
+                // without a handler `break` pauses the program, like a breakpoint
+                Err(fault @ Fault::Breakpoint { .. }) => {
+                    PLAY.store(false, Ordering::Relaxed);
+                    self.status = Some(format!("{}, continue with n or space", fault).yellow());
+                }
+                Err(fault) => {
+                    self.halt = true;
+                    PLAY.store(false, Ordering::Relaxed);
+                    self.status = Some(fault.to_string().red());
                 }
             }
         }
     }
 
+    /// The register bank that is shown, and edited
+    fn bank(&self) -> (&[u32; 32], &[u32; 32]) {
+        if self.show_fpu {
+            (&self.greg.freg, &self.prev_fregs)
+        } else {
+            (&self.greg.reg, &self.prev_regs)
+        }
+    }
+
+    fn bank_mut(&mut self) -> &mut [u32; 32] {
+        if self.show_fpu {
+            &mut self.greg.freg
+        } else {
+            &mut self.greg.reg
+        }
+    }
+
     fn run(mut self, mut terminal: DefaultTerminal) -> anyhow::Result<()> {
         loop {
             terminal.draw(|frame| self.draw(frame))?;
@@ -129,8 +191,18 @@ impl State {
                     }
 
                     match key.code {
+                        KeyCode::Esc if self.typing => self.typing = false,
+                        code if self.typing => self.type_key(code),
+                        KeyCode::Tab
+                            if !self.editing
+                                && self.greg.memory.devices().any(|(_, d)| d.takes_keys()) =>
+                        {
+                            self.typing = true;
+                        }
                         KeyCode::Char('d') if !self.editing => self.display_mode = DisplayMode::Dec,
                         KeyCode::Char('x') if !self.editing => self.display_mode = DisplayMode::Hex,
+                        KeyCode::Char('f') if !self.editing => self.show_fpu = !self.show_fpu,
+                        KeyCode::Char('p') if !self.editing => self.show_panels = !self.show_panels,
                         KeyCode::Char('j') | KeyCode::Down if !self.editing => {
                             self.curr_reg = self.curr_reg.saturating_add(1);
                         }
@@ -154,12 +226,16 @@ impl State {
                         }
                         KeyCode::Enter if self.editing => {
                             self.editing = false;
-                            self.greg.reg[self.curr_reg] = self.curr_buf;
+                            let curr_reg = self.curr_reg;
+                            self.bank_mut()[curr_reg] = self.curr_buf;
                             self.curr_buf = 0;
                         }
-                        KeyCode::Enter if !self.editing => {
+                        // $zero is hardwired, so it can not be edited
+                        KeyCode::Enter
+                            if !self.editing && (self.show_fpu || self.curr_reg != ZERO) =>
+                        {
                             self.editing = true;
-                            self.curr_buf = self.greg.reg[self.curr_reg];
+                            self.curr_buf = self.bank().0[self.curr_reg];
                         }
                         KeyCode::Esc if self.editing => {
                             self.editing = false;
@@ -208,25 +284,67 @@ impl State {
         style: Style,
         active_label: Option<&str>,
     ) {
-        let layout = Layout::horizontal([Constraint::Length(12), Constraint::Fill(1)]).split(rect);
+        let debug = self.greg.debug.as_ref();
+        // only programs assembled by greg have source to show
+        let source_width = if debug.is_some_and(|d| !d.source.is_empty()) {
+            1
+        } else {
+            0
+        };
+        let layout = Layout::horizontal([
+            Constraint::Length(12),
+            Constraint::Fill(1),
+            Constraint::Fill(source_width),
+        ])
+        .split(rect);
         frame.render_widget(
             Text::styled(format!("0x{:08x}", decomp.addr), style.fg(Color::DarkGray)),
             layout[0],
         );
         frame.render_widget(render_decomp(decomp, active_label).style(style), layout[1]);
+
+        // show the source on the first instruction that it produced
+        let source = match decomp.kind {
+            DecompKind::Label(_) => None,
+            _ => debug
+                .and_then(|d| d.source_at(decomp.addr))
+                .filter(|line| line.addrs.start == decomp.addr),
+        };
+        if let Some(source) = source {
+            frame.render_widget(
+                Text::styled(
+                    format!("{:>4}  {}", source.line, source.text),
+                    style.fg(Color::DarkGray),
+                ),
+                layout[2],
+            );
+        }
     }
 
     fn draw_registers(&self, frame: &mut Frame, rect: Rect) {
+        let (regs, prev_regs) = self.bank();
         let registers = Layout::vertical([Constraint::Length(1); 32]).split(rect);
         for (i, r) in registers.iter().enumerate() {
-            let row = Layout::horizontal([
-                Constraint::Fill(1),
-                Constraint::Length(12),
-                Constraint::Length(5),
-            ])
+            let row = Layout::horizontal(if self.show_fpu {
+                [
+                    Constraint::Length(4),
+                    Constraint::Length(12),
+                    Constraint::Fill(1),
+                ]
+            } else {
+                [
+                    Constraint::Fill(1),
+                    Constraint::Length(12),
+                    Constraint::Length(5),
+                ]
+            })
             .split(*r);
 
-            frame.render_widget(Reg::from(i as u32).into_span(), row[0]);
+            if self.show_fpu {
+                frame.render_widget(FReg(i as u8).into_span(), row[0]);
+            } else {
+                frame.render_widget(Reg::from(i as u32).into_span(), row[0]);
+            }
 
             let style = Style::default()
                 .fg(Color::Black)
@@ -235,17 +353,17 @@ impl State {
                 if self.editing {
                     (style.bg(Color::Green), self.curr_buf)
                 } else {
-                    (style.bg(Color::Magenta), self.greg.reg[i])
+                    (style.bg(Color::Magenta), regs[i])
                 }
             } else {
-                let style = if self.greg.reg[i] != self.prev_regs[i] {
+                let style = if regs[i] != prev_regs[i] {
                     style.bg(Color::Yellow)
-                } else if self.greg.reg[i] == 0 {
+                } else if regs[i] == 0 {
                     style.fg(Color::DarkGray)
                 } else {
                     style.fg(Color::Gray)
                 };
-                (style, self.greg.reg[i])
+                (style, regs[i])
             };
             frame.render_widget(
                 Text::styled(
@@ -257,7 +375,15 @@ impl State {
                 ),
                 row[1],
             );
-            if let Some(c) = get_showable_char(n) {
+            if self.show_fpu {
+                frame.render_widget(
+                    Text::styled(
+                        format!(" {}", java_float(f32::from_bits(n))),
+                        Style::default().fg(Color::Green),
+                    ),
+                    row[2],
+                );
+            } else if let Some(c) = get_showable_char(n) {
                 frame.render_widget(
                     Text::styled(format!(" {:?}", c), Style::default().fg(Color::Green)),
                     row[2],
@@ -278,70 +404,106 @@ impl State {
             DecompKind::Label(_) => false,
             _ => d.addr >= self.greg.ip, // >= in-case ip is not actually a statement for some reason
         });
+        // ip is past the last instruction once the program has finished
+        let (curr, active_label) = match curr {
+            Some((curr, active)) => (curr, active.active_label()),
+            None => (self.decomp.len(), None),
+        };
 
-        if let Some((curr, active)) = curr {
-            let active_label = active.active_label();
+        let start = curr.saturating_sub(before);
 
-            let start = curr.saturating_sub(before);
-
-            for i in 0..rect.height as usize {
-                if i + start >= self.decomp.len() {
-                    break;
-                }
-                let style = if i + start == curr {
-                    style.bg(Color::Indexed(237))
-                } else {
-                    style
-                };
-                self.draw_inst(&self.decomp[i + start], regs[i], frame, style, active_label);
+        for i in 0..rect.height as usize {
+            if i + start >= self.decomp.len() {
+                break;
             }
-        } else {
-            todo!()
+            let style = if i + start == curr {
+                style.bg(Color::Indexed(237))
+            } else {
+                style
+            };
+            self.draw_inst(&self.decomp[i + start], regs[i], frame, style, active_label);
         }
     }
 
     fn draw_stdout(&self, frame: &mut Frame, rect: Rect) {
-        // TODO: Fix this
-        let lines = self
-            .greg
-            .stdout
-            .as_ref()
-            .unwrap()
-            .lines()
-            .rev()
-            .take(rect.height as usize)
-            .collect::<Vec<_>>()
-            .into_iter()
-            .rev()
-            .collect::<Vec<_>>();
-        let s = lines.join("\n");
-        let nlines = lines.len();
-        let s = Text::styled(
-            format!("{}{}", "\n".repeat(rect.height as usize - nlines), s),
-            Style::new().gray(),
-        );
-        frame.render_widget(s, rect);
+        draw_tail(self.greg.stdout.as_ref().unwrap(), frame, rect);
+    }
+
+    fn draw_status(&self, frame: &mut Frame, rect: Rect) {
+        let status = match &self.status {
+            Some(status) => status.clone(),
+            None if self.typing => "typing to the devices, esc to stop".cyan(),
+            None => {
+                "space play/pause  n step  enter edit  f fpu  d/x dec/hex  +/- slower/faster  tab type  p panels  q quit"
+                    .dark_gray()
+            }
+        };
+        frame.render_widget(Line::from(status), rect);
     }
 
     fn draw(&self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+        self.draw_status(frame, status);
+
         let layout = Layout::horizontal([
             Constraint::Ratio(1, 6),
             Constraint::Fill(1),
             Constraint::Ratio(1, 4),
         ])
         .spacing(2)
-        .split(frame.area());
-
-        let block = title_block("Registers".into());
+        .split(main);
+
+        let block = if self.show_fpu {
+            let fcc = (0..8)
+                .rev()
+                .map(|cc| if self.greg.fcc(cc) { '1' } else { '0' })
+                .collect::<String>();
+            title_block(format!("FPU Registers (fcc {})", fcc))
+        } else {
+            title_block("Registers".into())
+        };
+        // the program break, where the heap ends
+        let block = block.title_bottom(format!("brk 0x{:08x}", self.greg.memory.heap.1));
         let reg_inner = block.inner(layout[0]);
         frame.render_widget(block, layout[0]);
         self.draw_registers(frame, reg_inner);
 
+        // devices with something to show get a panel each, next to the preview
+        let mut panels = self
+            .greg
+            .memory
+            .devices()
+            .filter(|_| self.show_panels)
+            .filter_map(|(_, device)| Some((device.panel()?, device)))
+            .collect::<Vec<_>>();
+        let [preview, panels_area] = if panels.is_empty() {
+            Layout::horizontal([Constraint::Fill(1), Constraint::Length(0)])
+        } else {
+            Layout::horizontal([Constraint::Fill(1), Constraint::Percentage(50)])
+        }
+        .spacing(2)
+        .areas(layout[1]);
+
         let block = title_block("Preview".into());
-        let preview_inner = block.inner(layout[1]);
-        frame.render_widget(block, layout[1]);
+        let preview_inner = block.inner(preview);
+        frame.render_widget(block, preview);
         self.draw_lines(frame, preview_inner);
 
+        let areas = Layout::vertical(panels.iter().map(|_| Constraint::Fill(1))).split(panels_area);
+        for ((title, device), area) in panels.iter_mut().zip(areas.iter()) {
+            let block = title_block(title.clone());
+            // the panels that keys are going to
+            let block = if self.typing && device.takes_keys() {
+                block.border_style(Style::new().cyan())
+            } else {
+                block
+            };
+            let inner = block.inner(*area);
+            frame.render_widget(block, *area);
+            device.draw(frame, inner);
+        }
+
         let block = title_block("STDOUT".into());
         let stdout = block.inner(layout[2]);
         frame.render_widget(block, layout[2]);
@@ -349,6 +511,26 @@ impl State {
     }
 }
 
+/// Draw the end of `text`, as much as fits
+pub fn draw_tail(text: &str, frame: &mut Frame, rect: Rect) {
+    // TODO: Fix this
+    let lines = text
+        .lines()
+        .rev()
+        .take(rect.height as usize)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect::<Vec<_>>();
+    let s = lines.join("\n");
+    let nlines = lines.len();
+    let s = Text::styled(
+        format!("{}{}", "\n".repeat(rect.height as usize - nlines), s),
+        Style::new().gray(),
+    );
+    frame.render_widget(s, rect);
+}
+
 fn title_block(title: String) -> Block<'static> {
     Block::new()
         .borders(Borders::ALL)
@@ -363,7 +545,31 @@ const INDENT: &str = "    ";
 fn render_decomp<'a>(decomp: &'a Decomp, active_label: Option<&'a str>) -> Line<'a> {
     let values = match &decomp.kind {
         DecompKind::Syscall => vec![INDENT.into(), "syscall".fg(Color::Magenta)],
+        DecompKind::Eret => vec![INDENT.into(), "eret".fg(Color::Magenta)],
         DecompKind::Nop => vec![INDENT.into(), "nop".fg(Color::DarkGray)],
+        DecompKind::Word(word) => vec![
+            INDENT.into(),
+            ".word".fg(Color::DarkGray),
+            " ".into(),
+            format!("0x{:08x}", word).into(),
+        ],
+        DecompKind::Sync { stype } => {
+            let mut values = vec![INDENT.into(), "sync".fg(Color::Magenta)];
+            if *stype != 0 {
+                values.extend([" ".into(), stype.to_string().into()]);
+            }
+            values
+        }
+        DecompKind::Break { code, subcode } => {
+            let mut values = vec![INDENT.into(), "break".fg(Color::Red)];
+            if *code != 0 || *subcode != 0 {
+                values.extend([" ".into(), code.to_string().into()]);
+            }
+            if *subcode != 0 {
+                values.extend([", ".into(), subcode.to_string().into()]);
+            }
+            values
+        }
         DecompKind::Label(l) => {
             vec![
                 if Some(l.as_str()) == active_label {
@@ -420,6 +626,54 @@ fn render_decomp<'a>(decomp: &'a Decomp, active_label: Option<&'a str>) -> Line<
                 s.into(),
             ]
         }
+        DecompKind::Unary { f, d, s } => {
+            vec![
+                INDENT.into(),
+                f.inst_name().into(),
+                " ".into(),
+                d.into(),
+                ", ".into(),
+                s.into(),
+            ]
+        }
+        DecompKind::BitField { f, t, s, pos, size } => {
+            vec![
+                INDENT.into(),
+                f.inst_name().into(),
+                " ".into(),
+                t.into(),
+                ", ".into(),
+                s.into(),
+                ", ".into(),
+                pos.to_string().into(),
+                ", ".into(),
+                size.to_string().into(),
+            ]
+        }
+        DecompKind::Trap { f, s, t, code } => {
+            let mut values = vec![
+                INDENT.into(),
+                f.inst_name().into(),
+                " ".into(),
+                s.into(),
+                ", ".into(),
+                t.into(),
+            ];
+            if *code != 0 {
+                values.extend([", ".into(), code.to_string().into()]);
+            }
+            values
+        }
+        DecompKind::TrapI { o, s, i } => {
+            vec![
+                INDENT.into(),
+                o.inst_name().into(),
+                " ".into(),
+                s.into(),
+                ", ".into(),
+                i.to_string().into(),
+            ]
+        }
         DecompKind::JumpR { f, s } => {
             vec![INDENT.into(), f.inst_name().into(), " ".into(), s.into()]
         }
@@ -455,6 +709,7 @@ fn render_decomp<'a>(decomp: &'a Decomp, active_label: Option<&'a str>) -> Line<
             let label = match pos {
                 Addr::Label(l) => l.to_string().fg(Color::Yellow),
                 Addr::Relative(n) => n.to_string().into(),
+                Addr::Absolute(addr) => format!("0x{:08x}", addr).into(),
             };
             vec![
                 INDENT.into(),
@@ -471,6 +726,7 @@ fn render_decomp<'a>(decomp: &'a Decomp, active_label: Option<&'a str>) -> Line<
             let label = match pos {
                 Addr::Label(l) => l.to_string().fg(Color::Yellow),
                 Addr::Relative(n) => n.to_string().into(),
+                Addr::Absolute(addr) => format!("0x{:08x}", addr).into(),
             };
             vec![
                 INDENT.into(),
@@ -494,13 +750,104 @@ fn render_decomp<'a>(decomp: &'a Decomp, active_label: Option<&'a str>) -> Line<
                 ")".into(),
             ]
         }
+        DecompKind::Cache { o, op, s, i } => {
+            vec![
+                INDENT.into(),
+                o.inst_name().into(),
+                " ".into(),
+                op.to_string().into(),
+                ", ".into(),
+                i.to_string().into(),
+                "(".into(),
+                s.into(),
+                ")".into(),
+            ]
+        }
         DecompKind::Jump { o, pos } => {
             let label = match pos {
                 Addr::Label(l) => l.to_string().fg(Color::Yellow),
                 Addr::Relative(n) => n.to_string().into(),
+                Addr::Absolute(addr) => format!("0x{:08x}", addr).into(),
             };
             vec![INDENT.into(), o.inst_name().into(), " ".into(), label]
         }
+        DecompKind::Cop0Move { f, t, d } => {
+            vec![
+                INDENT.into(),
+                f.inst_name().into(),
+                " ".into(),
+                t.into(),
+                ", ".into(),
+                format!("${}", d).fg(Color::Magenta),
+            ]
+        }
+        DecompKind::FpuArith { f, d, s, t } => {
+            vec![
+                INDENT.into(),
+                f.fpu_name().into(),
+                " ".into(),
+                d.into(),
+                ", ".into(),
+                s.into(),
+                ", ".into(),
+                t.into(),
+            ]
+        }
+        DecompKind::FpuUnary { f, d, s } => {
+            vec![
+                INDENT.into(),
+                f.fpu_name().into(),
+                " ".into(),
+                d.into(),
+                ", ".into(),
+                s.into(),
+            ]
+        }
+        DecompKind::FpuCompare { f, cc, s, t } => {
+            let mut values = vec![INDENT.into(), f.fpu_name().into(), " ".into()];
+            // flag 0 is implied when it is left out
+            if *cc != 0 {
+                values.extend([cc.to_string().into(), ", ".into()]);
+            }
+            values.extend([s.into(), ", ".into(), t.into()]);
+            values
+        }
+        DecompKind::FpuMove { f, t, s } => {
+            vec![
+                INDENT.into(),
+                f.fpu_name().into(),
+                " ".into(),
+                t.into(),
+                ", ".into(),
+                s.into(),
+            ]
+        }
+        DecompKind::FpuBranch { o, cc, pos } => {
+            let label = match pos {
+                Addr::Label(l) => l.to_string().fg(Color::Yellow),
+                Addr::Relative(n) => n.to_string().into(),
+                Addr::Absolute(addr) => format!("0x{:08x}", addr).into(),
+            };
+            let mut values = vec![INDENT.into(), o.fpu_name().into(), " ".into()];
+            if *cc != 0 {
+                values.extend([cc.to_string().into(), ", ".into()]);
+            }
+            values.push(label);
+            values
+        }
+        DecompKind::FpuLoadStore { o, s, t, i } => {
+            vec![
+                INDENT.into(),
+                o.inst_name().fg(Color::Red),
+                " ".into(),
+                t.into(),
+                ", ".into(),
+                i.to_string().into(),
+                "(".into(),
+                s.into(),
+                ")".into(),
+            ]
+        }
     };
     Line::from(values)
 }
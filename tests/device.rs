@@ -0,0 +1,74 @@
+//! A device from outside the crate, attached the way the bitmap display is
+
+use greg::{memmap::MemoryMap, mmio::Device, Greg, InstructionResult};
+
+/// Counts the reads of it, a write sets the count.  It interrupts once it gets to 10.
+#[derive(Debug, Default)]
+struct Counter {
+    count: u32,
+}
+
+impl Device for Counter {
+    fn name(&self) -> &'static str {
+        "counter"
+    }
+
+    fn peek(&self, _offset: usize, _size: usize) -> u32 {
+        self.count
+    }
+
+    fn read(&mut self, _offset: usize, _size: usize) -> u32 {
+        self.count += 1;
+        self.count
+    }
+
+    fn write(&mut self, _offset: usize, _size: usize, value: u32) {
+        self.count = value;
+    }
+
+    fn interrupt(&self) -> bool {
+        self.count >= 10
+    }
+}
+
+const PROGRAM: &str = "
+.text
+    li $t0, 0xffff1000
+    li $t1, 5
+    sw $t1, 0($t0)
+    lw $a0, 0($t0)
+    li $v0, 1
+    syscall
+    # enable interrupts on line 2
+    li $t1, 0x401
+    mtc0 $t1, $12
+spin:
+    lw $t1, 0($t0)
+    j spin
+
+.ktext 0x80000180
+    mfc0 $a0, $13
+    li $v0, 34
+    syscall
+    li $v0, 10
+    syscall
+";
+
+#[test]
+fn custom_device() {
+    let mut greg = Greg::from_asm(PROGRAM, &MemoryMap::MARS, false).unwrap();
+    let counter = Box::new(Counter::default());
+    greg.memory
+        .attach(0xffff_1000..0xffff_1004, Some(2), counter)
+        .unwrap();
+    greg.stdout = Some(String::new());
+    for _ in 0..1000 {
+        if !matches!(greg.step().unwrap(), InstructionResult::None) {
+            break;
+        }
+    }
+    // the store set the count and the load counted itself, then spinning counted up to 10 and
+    // the interrupt is pending on line 2 in Cause
+    assert_eq!(greg.memory.device::<Counter>().unwrap().count, 10);
+    assert!(greg.stdout.unwrap().starts_with("60x00000400"));
+}